@@ -0,0 +1,213 @@
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+/// How player slots are split between jobs when their shares aren't whole numbers.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Apportionment {
+    /// Whole parts first, remaining slots go to the largest fractions, ties are random.
+    #[default]
+    LargestRemainder,
+    /// Highest averages with divisors 1, 2, 3, ..., favours jobs with large shares.
+    DHondt,
+    /// Highest averages with divisors 1, 3, 5, ..., closer to proportional for small shares.
+    SainteLague,
+    /// Whole parts first, remaining slots go to the largest fractions multiplied by the job's priority.
+    WeightedPriority,
+}
+
+impl Apportionment {
+    pub const ALL: [Apportionment; 4] = [
+        Self::LargestRemainder,
+        Self::DHondt,
+        Self::SainteLague,
+        Self::WeightedPriority,
+    ];
+
+    /// Name used in commands and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::LargestRemainder => "largest-remainder",
+            Self::DHondt => "dhondt",
+            Self::SainteLague => "sainte-lague",
+            Self::WeightedPriority => "weighted-priority",
+        }
+    }
+}
+
+impl fmt::Display for Apportionment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Apportionment {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|method| method.name() == name)
+            .ok_or(())
+    }
+}
+
+/// Splits `amount` slots between shares given as `(share, priority)`.
+/// Returns the slots of every share in the same order.
+pub(crate) fn apportion<R: Rng + ?Sized>(
+    shares: &[(f64, u16)],
+    amount: u16,
+    method: Apportionment,
+    rng: &mut R,
+) -> Vec<u16> {
+    match method {
+        Apportionment::LargestRemainder => {
+            largest_remainder(shares, amount, rng, |share, _priority| share.fract())
+        }
+        Apportionment::WeightedPriority => largest_remainder(shares, amount, rng, |share, priority| {
+            share.fract() * priority as f64
+        }),
+        Apportionment::DHondt => highest_averages(shares, amount, rng, |seats| seats as f64 + 1.),
+        Apportionment::SainteLague => {
+            highest_averages(shares, amount, rng, |seats| 2. * seats as f64 + 1.)
+        }
+    }
+}
+
+/// Gives whole parts of the shares, then one more slot to shares in order of their score.
+/// Whole parts adding up to more than the amount lose slots from the lowest scores instead.
+fn largest_remainder<R: Rng + ?Sized>(
+    shares: &[(f64, u16)],
+    amount: u16,
+    rng: &mut R,
+    score: impl Fn(f64, u16) -> f64,
+) -> Vec<u16> {
+    let mut slots: Vec<u16> = shares.iter().map(|(share, _priority)| share.trunc() as u16).collect();
+    let used: u16 = slots.iter().sum();
+
+    // Shuffled before a stable sort so equal scores end up in random order
+    let mut order: Vec<usize> = (0..shares.len()).collect();
+    order.shuffle(rng);
+    order.sort_by(|a, b| {
+        let (share_a, priority_a) = shares[*a];
+        let (share_b, priority_b) = shares[*b];
+        score(share_b, priority_b)
+            .partial_cmp(&score(share_a, priority_a))
+            .unwrap_or(Ordering::Equal)
+    });
+    let mut excess = used.saturating_sub(amount);
+    for index in order.iter().rev().cycle() {
+        if excess == 0 {
+            break;
+        }
+        if slots[*index] > 0 {
+            slots[*index] -= 1;
+            excess -= 1;
+        }
+    }
+    for index in order.into_iter().cycle().take(amount.saturating_sub(used) as usize) {
+        slots[index] += 1;
+    }
+    slots
+}
+
+/// Gives slots one by one to the share with the highest share divided by `divisor(slots so far)`.
+/// Without any share above 0 there is nothing to divide, so slots are split evenly instead.
+fn highest_averages<R: Rng + ?Sized>(
+    shares: &[(f64, u16)],
+    amount: u16,
+    rng: &mut R,
+    divisor: impl Fn(u16) -> f64,
+) -> Vec<u16> {
+    if shares.iter().all(|(share, _priority)| *share <= 0.) {
+        return largest_remainder(shares, amount, rng, |share, _priority| share.fract());
+    }
+    let mut slots = vec![0; shares.len()];
+    for _ in 0..amount {
+        let averages: Vec<f64> = shares
+            .iter()
+            .zip(slots.iter())
+            .map(|((share, _priority), slots)| share / divisor(*slots))
+            .collect();
+        let best = averages.iter().copied().fold(0., f64::max);
+        let tied: Vec<usize> = (0..shares.len())
+            .filter(|index| (best - averages[*index]).abs() < f64::EPSILON)
+            .collect();
+        slots[*tied.choose(rng).unwrap()] += 1;
+    }
+    slots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn apportion_seeded(shares: &[(f64, u16)], amount: u16, method: Apportionment) -> Vec<u16> {
+        apportion(shares, amount, method, &mut StdRng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn largest_remainder_prefers_largest_fractions() {
+        let shares = [(1.2, 1), (2.5, 1), (1.3, 1)];
+
+        assert_eq!(apportion_seeded(&shares, 5, Apportionment::LargestRemainder), vec![1, 3, 1]);
+    }
+
+    #[test]
+    fn largest_remainder_breaks_ties_randomly() {
+        let shares = [(0.5, 1), (0.5, 1)];
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let firsts = (0..100)
+            .filter(|_| apportion(&shares, 1, Apportionment::LargestRemainder, &mut rng)[0] == 1)
+            .count();
+
+        assert!(firsts > 20 && firsts < 80);
+    }
+
+    #[test]
+    fn largest_remainder_never_exceeds_amount() {
+        let shares = [(2.7, 1), (2.2, 1)];
+
+        for method in [Apportionment::LargestRemainder, Apportionment::WeightedPriority].iter() {
+            assert_eq!(apportion_seeded(&shares, 3, *method), vec![2, 1], "{}", method);
+        }
+    }
+
+    #[test]
+    fn weighted_priority_scales_fractions() {
+        let shares = [(1.2, 3), (2.5, 1)];
+
+        assert_eq!(apportion_seeded(&shares, 4, Apportionment::WeightedPriority), vec![2, 2]);
+        assert_eq!(apportion_seeded(&shares, 4, Apportionment::LargestRemainder), vec![1, 3]);
+    }
+
+    #[test]
+    fn highest_averages_differ_on_small_shares() {
+        let shares = [(5.4, 1), (1.6, 1)];
+
+        assert_eq!(apportion_seeded(&shares, 7, Apportionment::DHondt), vec![6, 1]);
+        assert_eq!(apportion_seeded(&shares, 7, Apportionment::SainteLague), vec![5, 2]);
+    }
+
+    #[test]
+    fn gives_out_every_slot_without_shares() {
+        let shares = [(0., 1), (0., 2)];
+
+        for method in Apportionment::ALL.iter() {
+            let slots = apportion_seeded(&shares, 3, *method);
+            assert_eq!(slots.iter().sum::<u16>(), 3, "{}", method);
+            assert!(slots.iter().all(|slots| *slots >= 1), "{}", method);
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for method in Apportionment::ALL.iter() {
+            assert_eq!(method.name().parse::<Apportionment>(), Ok(*method));
+        }
+        assert!("fair".parse::<Apportionment>().is_err());
+    }
+}
@@ -0,0 +1,14 @@
+//! Role dispatching algorithm, independent of Discord.
+//!
+//! Players and jobs are identified by any copyable, hashable ids.
+//! Each job has [`Stages`] with the amount of players it needs for some player counts,
+//! [`decide_quotas`] turns them into amounts for the actual player count
+//! using an [`Apportionment`] method and [`decide_pairings`] assigns qualified players to fill those amounts.
+
+mod apportionment;
+mod pairings;
+mod stages;
+
+pub use apportionment::Apportionment;
+pub use pairings::{decide_pairings, decide_quotas, remove_irrelevant_qualifications, PairingError};
+pub use stages::Stages;
@@ -0,0 +1,257 @@
+use indexmap::{IndexMap, IndexSet};
+use rand::{distributions::Uniform, prelude::Distribution, Rng};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use crate::{apportionment::apportion, Apportionment, Stages};
+
+/// Decide how many players each job needs.
+/// Jobs are given with their stages and priority,
+/// the interpolated amounts are split into whole slots by the apportionment method.
+pub fn decide_quotas<'a, J, R>(
+    jobs: impl IntoIterator<Item = (J, &'a mut Stages, u16)>,
+    amount: u16,
+    method: Apportionment,
+    rng: &mut R,
+) -> IndexMap<J, u16>
+where
+    J: Copy + Eq + Hash,
+    R: Rng + ?Sized,
+{
+    let (job_ids, shares): (Vec<J>, Vec<(f64, u16)>) = jobs
+        .into_iter()
+        .map(|(job_id, stages, priority)| (job_id, (stages.interpolate(amount), priority)))
+        .unzip();
+    let slots = apportion(&shares, amount, method, rng);
+    job_ids.into_iter().zip(slots).collect()
+}
+
+/// Why players couldn't be paired with jobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairingError<J> {
+    /// Not enough players are qualified for the job.
+    Unqualified(J),
+    /// Every job is filled but some players are left without one.
+    NoSlotsLeft,
+}
+
+/// Decide pairings.
+/// Fill the quotas of every job with players qualified for it,
+/// applying last step of Hungarian Algorithm.
+/// Players are given with the tier of each of their qualifications, 0 being the highest,
+/// a job is filled by players of the highest tier available before falling back to lower ones.
+/// Returns why the assignment failed if there was a problem during assignment.
+pub fn decide_pairings<P, J, R>(
+    quotas: IndexMap<J, u16>,
+    players: &HashMap<P, HashMap<J, u8>>,
+    rng: &mut R,
+) -> Result<IndexMap<J, Vec<P>>, PairingError<J>>
+where
+    P: Copy + Eq + Hash,
+    J: Copy + Eq + Hash,
+    R: Rng + ?Sized,
+{
+    /// Variables required for assigning jobs.
+    #[derive(Debug)]
+    struct AssigningJob<P> {
+        needed: u16,
+        players: IndexSet<P>,
+    }
+
+    /// Assigns a player to a job.
+    /// Update all the variables.
+    /// Remove used players and jobs.
+    fn assign<P: Copy + Eq + Hash, J: Copy + Eq + Hash>(
+        assigned: &mut IndexMap<J, Vec<P>>,
+        left_jobs: &mut IndexMap<J, AssigningJob<P>>,
+        left_players: &mut IndexSet<P>,
+        job_id: J,
+        player_id: P,
+    ) {
+        // Update `assigned`
+        assigned.entry(job_id).or_default().push(player_id);
+        // Update `left_jobs`
+        let job = left_jobs.get_mut(&job_id).unwrap();
+        job.needed -= 1;
+        if job.needed == 0 {
+            left_jobs.remove(&job_id);
+        }
+        left_jobs.iter_mut().for_each(|(_job_id, job)| {
+            job.players.remove(&player_id);
+        });
+        // Update `left_players`
+        left_players.remove(&player_id);
+    }
+
+    // Initialize assignment variables
+    let mut assigned: IndexMap<J, Vec<P>> = IndexMap::new();
+    let mut left_jobs: IndexMap<J, AssigningJob<P>> = quotas
+        .into_iter()
+        .map(|(job_id, needed)| {
+            (
+                job_id,
+                AssigningJob {
+                    needed,
+                    players: players
+                        .iter()
+                        .filter(|(_player_id, jobs)| jobs.contains_key(&job_id))
+                        .map(|(player_id, _jobs)| *player_id)
+                        .collect(),
+                },
+            )
+        })
+        .collect();
+
+    // Initialize more assignment variables
+    let mut left_players: IndexSet<P> = players.keys().copied().collect();
+    // Assign players to jobs
+    for _ in 0..left_players.len() {
+        let (job_id, job) = match left_jobs
+            .iter()
+            .filter(|(_job_id, job)| job.needed > 0)
+            .min_by_key(|(_job_id, job)| job.players.len())
+        {
+            Some(left_job) => left_job,
+            None => return Err(PairingError::NoSlotsLeft),
+        };
+        let job_id = *job_id;
+        // Check if any players are available
+        if job.players.is_empty() {
+            return Err(PairingError::Unqualified(job_id));
+        }
+        // Only players of the highest tier left are considered
+        let tier = |player_id: &P| players[player_id][&job_id];
+        let best = job.players.iter().map(tier).min().unwrap();
+        let candidates: Vec<P> = job
+            .players
+            .iter()
+            .filter(|player_id| tier(player_id) == best)
+            .copied()
+            .collect();
+        let player_id = candidates[Uniform::from(0..candidates.len()).sample(rng)];
+        assign(
+            &mut assigned,
+            &mut left_jobs,
+            &mut left_players,
+            job_id,
+            player_id,
+        );
+    }
+    Ok(assigned)
+}
+
+/// Remove qualifications for things that aren't jobs and players without any left.
+pub fn remove_irrelevant_qualifications<P, J>(
+    players: &mut HashMap<P, HashSet<J>>,
+    is_job: impl Fn(&J) -> bool,
+) where
+    P: Eq + Hash,
+    J: Eq + Hash,
+{
+    for (_id, jobs) in players.iter_mut() {
+        jobs.retain(|job_id| is_job(job_id));
+    }
+    players.retain(|_player_id, jobs| {
+        !jobs.is_empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Players qualified for every job with the same tier.
+    fn qualified_players(count: u32, jobs: &[char]) -> HashMap<u32, HashMap<char, u8>> {
+        (0..count)
+            .map(|player_id| (player_id, jobs.iter().map(|job_id| (*job_id, 0)).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn quotas_follow_stages() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let mut runners: Stages = vec![(4, 3), (8, 6)].into_iter().collect();
+        let mut readers: Stages = vec![(4, 1), (8, 2)].into_iter().collect();
+
+        let quotas = decide_quotas(
+            vec![('r', &mut runners, 1), ('b', &mut readers, 1)],
+            5,
+            Apportionment::LargestRemainder,
+            rng,
+        );
+
+        // 3.75 runners and 1.25 readers, the leftover goes to the larger fraction
+        assert_eq!(quotas[&'r'], 4);
+        assert_eq!(quotas[&'b'], 1);
+    }
+
+    #[test]
+    fn fills_every_quota_with_qualified_players() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let quotas: IndexMap<char, u16> = vec![('a', 1), ('b', 2)].into_iter().collect();
+        let mut players = qualified_players(2, &['b']);
+        players.insert(2, vec![('a', 0)].into_iter().collect());
+
+        let assigned = decide_pairings(quotas, &players, rng).unwrap();
+
+        assert_eq!(assigned[&'a'], vec![2]);
+        let mut readers = assigned[&'b'].clone();
+        readers.sort_unstable();
+        assert_eq!(readers, vec![0, 1]);
+    }
+
+    #[test]
+    fn reports_jobs_without_enough_qualified_players() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let quotas: IndexMap<char, u16> = vec![('a', 2), ('b', 1)].into_iter().collect();
+        let mut players = qualified_players(2, &['b']);
+        players.insert(2, vec![('a', 0)].into_iter().collect());
+
+        let assigned = decide_pairings(quotas, &players, rng);
+
+        assert_eq!(assigned, Err(PairingError::Unqualified('a')));
+    }
+
+    #[test]
+    fn removes_irrelevant_qualifications() {
+        let mut players: HashMap<u32, HashSet<char>> = vec![
+            (0, vec!['a', 'x'].into_iter().collect()),
+            (1, vec!['x'].into_iter().collect()),
+        ]
+        .into_iter()
+        .collect();
+
+        remove_irrelevant_qualifications(&mut players, |job_id| *job_id == 'a');
+
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[&0], vec!['a'].into_iter().collect());
+    }
+
+    #[test]
+    fn assigns_everyone_below_the_first_stage() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let players = qualified_players(2, &['a']);
+
+        for method in Apportionment::ALL.iter() {
+            let mut stages: Stages = vec![(4, 4)].into_iter().collect();
+            let quotas = decide_quotas(vec![('a', &mut stages, 1)], 2, *method, rng);
+            assert_eq!(quotas[&'a'], 2, "{}", method);
+
+            let assigned = decide_pairings(quotas, &players, rng).unwrap();
+            assert_eq!(assigned[&'a'].len(), 2, "{}", method);
+        }
+    }
+
+    #[test]
+    fn reports_players_left_without_slots() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let quotas: IndexMap<char, u16> = vec![('a', 1)].into_iter().collect();
+
+        let assigned = decide_pairings(quotas, &qualified_players(2, &['a']), rng);
+
+        assert_eq!(assigned, Err(PairingError::NoSlotsLeft));
+    }
+}
@@ -0,0 +1,122 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    iter::FromIterator,
+    ops::{Deref, DerefMut},
+};
+
+/// Amount of players a job needs for specific player counts.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(transparent)]
+pub struct Stages(IndexMap<u16, u16>);
+
+impl Stages {
+    /// Amount of players needed for a player count,
+    /// interpolated linearly between the closest stages.
+    pub fn interpolate(&mut self, player_count: u16) -> f64 {
+        self.sort_keys();
+        if let Some(amount) = self.get(&player_count) {
+            return *amount as f64
+        }
+        // No existing value, interpolate between the closest stages below and above
+        let mut bottom: Option<(u16, u16)> = None;
+        let mut top: Option<(u16, u16)> = None;
+        for (point_player_count, point_amount) in self.iter() {
+            if *point_player_count < player_count {
+                bottom = Some((*point_player_count, *point_amount));
+            } else {
+                top = Some((*point_player_count, *point_amount));
+                break
+            }
+        }
+
+        match (bottom, top) {
+            (Some((bottom_player_count, bottom_amount)), Some((top_player_count, top_amount))) => {
+                let player_diff = top_player_count as f64 - bottom_player_count as f64;
+                let amount_diff = top_amount as f64 - bottom_amount as f64;
+                let scale = amount_diff / player_diff;
+                bottom_amount as f64 + (player_count - bottom_player_count) as f64 * scale
+            },
+            (Some((bottom_player_count, bottom_amount)), None) => {
+                let player_diff = bottom_player_count as f64;
+                let amount_diff = bottom_amount as f64;
+                let scale = amount_diff / player_diff;
+                bottom_amount as f64 + (player_count - bottom_player_count) as f64 * scale
+            },
+            (None, Some((top_player_count, top_amount))) => {
+                let player_diff = top_player_count as f64;
+                let amount_diff = top_amount as f64;
+                let scale = amount_diff / player_diff;
+                player_count as f64 * scale
+            },
+            (None, None) => {
+                0.
+            },
+        }
+    }
+}
+
+impl Deref for Stages {
+    type Target = IndexMap<u16, u16>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Stages {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl FromIterator<(u16, u16)> for Stages {
+    fn from_iter<I: IntoIterator<Item = (u16, u16)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for Stages {
+    type Item = (u16, u16);
+    type IntoIter = indexmap::map::IntoIter<u16, u16>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stages() -> Stages {
+        vec![(8, 4), (4, 1)].into_iter().collect()
+    }
+
+    #[test]
+    fn uses_exact_stages() {
+        assert_eq!(stages().interpolate(4), 1.);
+        assert_eq!(stages().interpolate(8), 4.);
+    }
+
+    #[test]
+    fn scales_below_the_first_stage() {
+        assert_eq!(stages().interpolate(2), 0.5);
+    }
+
+    #[test]
+    fn interpolates_between_stages() {
+        assert_eq!(stages().interpolate(6), 2.5);
+        assert_eq!(stages().interpolate(7), 3.25);
+    }
+
+    #[test]
+    fn scales_above_the_last_stage() {
+        assert_eq!(stages().interpolate(12), 6.);
+    }
+
+    #[test]
+    fn needs_nobody_without_stages() {
+        assert_eq!(Stages::default().interpolate(5), 0.);
+    }
+}
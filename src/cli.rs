@@ -0,0 +1,175 @@
+//! Offline roller using the bot's algorithm without connecting to Discord.
+//!
+//! `role_dispatch roll <players.ron> [--jobs <jobs.ron>] [--method <method>] [--json]`
+//!
+//! The players file maps each player to the ids of the roles they are qualified for:
+//! `{ "Alice": [123, 456], "Bob": [456] }`.
+
+use indexmap::IndexMap;
+use role_dispatch_core::Apportionment;
+use serenity::model::prelude::*;
+use std::{fmt::Write, fs::File};
+
+use crate::{
+    commands::{
+        roll::{decide_pairings, describe_failure},
+        util::remove_irrelevant_qualifications,
+    },
+    model::{data_path, Jobs, JOBS_FILE},
+};
+
+const USAGE: &str = "Usage: role_dispatch roll <players.ron> [--jobs <jobs.ron>] [--method <method>] [--json]";
+
+/// Players and the ids of roles they are qualified for.
+type Players = IndexMap<String, Vec<u64>>;
+
+/// Runs a command line subcommand if one was given, returning the exit code.
+pub fn run(mut args: impl Iterator<Item = String>) -> Option<i32> {
+    match args.next().as_deref() {
+        Some("roll") => Some(match roll(args) {
+            Ok(output) => {
+                print!("{}", output);
+                0
+            }
+            Err(why) => {
+                eprintln!("{}", why);
+                1
+            }
+        }),
+        _ => None,
+    }
+}
+
+fn roll(mut args: impl Iterator<Item = String>) -> Result<String, String> {
+    let mut players_path = None;
+    let mut jobs_path = data_path(JOBS_FILE).to_string_lossy().into_owned();
+    let mut method = Apportionment::default();
+    let mut json = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--jobs" => jobs_path = args.next().ok_or(USAGE)?,
+            "--method" => {
+                let name = args.next().ok_or(USAGE)?;
+                method = name.parse().map_err(|_| format!("Unknown apportionment method '{}'.", name))?;
+            }
+            _ if players_path.is_none() && !arg.starts_with("--") => players_path = Some(arg),
+            _ => return Err(USAGE.to_owned()),
+        }
+    }
+    let players_path = players_path.ok_or(USAGE)?;
+
+    let mut jobs: Jobs = read_ron(&jobs_path)?;
+    let players: Players = read_ron(&players_path)?;
+
+    // Players are identified by their position in the file.
+    let names: Vec<&String> = players.keys().collect();
+    let mut users_roles = players
+        .values()
+        .enumerate()
+        .map(|(index, role_ids)| {
+            let user_id = UserId(index as u64);
+            (user_id, role_ids.iter().map(|role_id| RoleId(*role_id)).collect())
+        })
+        .collect();
+    remove_irrelevant_qualifications(&mut users_roles, &jobs);
+
+    let mut assigned = decide_pairings(&mut jobs, &users_roles, method)
+        .map_err(|why| describe_failure(why, |role_id| role_id.to_string()))?;
+    assigned.sort_keys();
+    let assigned: IndexMap<String, Vec<&String>> = assigned
+        .into_iter()
+        .map(|(role_id, players)| {
+            let players = players.into_iter().map(|user_id| names[user_id.0 as usize]).collect();
+            (role_id.to_string(), players)
+        })
+        .collect();
+
+    if json {
+        return Ok(format!("{}\n", serde_json::json!(assigned)));
+    }
+    let mut output = String::new();
+    for (role_id, players) in assigned {
+        output.write_fmt(format_args!("{}:\n", role_id)).ok();
+        for player in players {
+            output.write_fmt(format_args!("- {},\n", player)).ok();
+        }
+        output.write_str("\n").ok();
+    }
+    Ok(output)
+}
+
+fn read_ron<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, String> {
+    let file = File::open(path).map_err(|why| format!("Could not open '{}': {}", path, why))?;
+    ron::de::from_reader(file).map_err(|why| format!("Could not read '{}': {}", path, why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf};
+
+    /// Writes a file for a single test into the temporary directory.
+    fn temp_file(name: &str, content: &str) -> String {
+        let path: PathBuf = std::env::temp_dir().join(format!("role_dispatch_{}_{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Jobs which each need one of two players, and players qualified for one job each.
+    fn jobs_and_players(name: &str) -> (String, String) {
+        let jobs = temp_file(&format!("{}_jobs.ron", name), "{ 1: (points: { 2: 1 }), 2: (points: { 2: 1 }) }");
+        let players = temp_file(&format!("{}_players.ron", name), r#"{ "Alice": [1], "Bob": [2] }"#);
+        (jobs, players)
+    }
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn rolls_as_text() {
+        let (jobs, players) = jobs_and_players("text");
+
+        let output = roll(args(&[&players, "--jobs", &jobs])).unwrap();
+
+        assert_eq!(output, "1:\n- Alice,\n\n2:\n- Bob,\n\n");
+    }
+
+    #[test]
+    fn rolls_as_json() {
+        let (jobs, players) = jobs_and_players("json");
+
+        let output = roll(args(&[&players, "--jobs", &jobs, "--method", "dhondt", "--json"])).unwrap();
+
+        assert_eq!(output, "{\"1\":[\"Alice\"],\"2\":[\"Bob\"]}\n");
+    }
+
+    #[test]
+    fn rejects_unknown_method() {
+        let (jobs, players) = jobs_and_players("method");
+
+        let reply = roll(args(&[&players, "--jobs", &jobs, "--method", "fair"]));
+
+        assert_eq!(reply.unwrap_err(), "Unknown apportionment method 'fair'.");
+    }
+
+    #[test]
+    fn requires_players() {
+        let (jobs, _players) = jobs_and_players("usage");
+
+        assert_eq!(roll(args(&["--jobs", &jobs])).unwrap_err(), USAGE);
+        let missing = roll(args(&["missing_players.ron", "--jobs", &jobs]));
+        assert!(missing.unwrap_err().starts_with("Could not open 'missing_players.ron'"));
+    }
+
+    #[test]
+    fn reports_unreadable_files() {
+        let (jobs, _players) = jobs_and_players("unreadable");
+        let players = temp_file("unreadable_broken.ron", "{ \"Alice\": [1 }");
+
+        let reply = roll(args(&[&players, "--jobs", &jobs]));
+
+        assert!(reply.unwrap_err().starts_with(&format!("Could not read '{}'", players)));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::model::load_guild_config;
+use serenity::{
+    framework::standard::{macros::check, Args, CommandOptions, Reason},
+    model::prelude::*,
+    prelude::*,
+};
+
+#[check]
+#[name = "Manager"]
+async fn manager_check(
+    ctx: &Context,
+    msg: &Message,
+    _: &mut Args,
+    _: &CommandOptions,
+) -> Result<(), Reason> {
+    match is_manager(ctx, msg).await {
+        true => Ok(()),
+        false => Err(Reason::User(
+            "Only members with the manager role or the Manage Server permission can change the configuration."
+                .to_owned(),
+        )),
+    }
+}
+
+/// Returns whether the caller can change the configuration.
+/// Administrators and members who can manage the server always can,
+/// other members need the configured manager role.
+pub async fn is_manager(ctx: &Context, msg: &Message) -> bool {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return false,
+    };
+    let member = match msg.member(ctx).await {
+        Ok(member) => member,
+        Err(_) => return false,
+    };
+    if let Some(manager_role) = load_guild_config(guild_id.0).manager_role {
+        if member.roles.contains(&RoleId(manager_role)) {
+            return true;
+        }
+    }
+    member
+        .permissions(ctx)
+        .await
+        .map(|permissions| permissions.administrator() || permissions.manage_guild())
+        .unwrap_or(false)
+}
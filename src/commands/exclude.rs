@@ -1,57 +1,391 @@
-use crate::model::{load_excluded, save_excluded};
+use crate::{
+    error::BotError,
+    model::{
+        load_exclusions, load_guild_config, load_guild_exclusions, load_session,
+        lock_guild_exclusions, save_guild_exclusions, update_guild_config, Exclusion,
+        GuildExclusions,
+    },
+};
 use serenity::{
     framework::standard::{macros::command, Args, CommandResult},
     model::prelude::*,
     prelude::*,
 };
+use std::fmt::Write;
+
+use super::{
+    checks::is_manager,
+    guild::{DiscordGuild, GuildAccess},
+    util::{parse_duration, unix_now, Reply},
+};
 
 #[command]
-#[description("Toggle between being exluded and included in role distribution.")]
-async fn exclude(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let guild_id = msg.guild_id.unwrap();
-
-    let role_id = match load_excluded() {
-        Some(excluded) => RoleId(excluded),
-        None => {
-            let role_id = guild_id
-                .create_role(&ctx.http, |e| e.name("Excluded"))
-                .await
-                .unwrap()
-                .id;
-            save_excluded(role_id.0);
-            role_id
+#[only_in(guilds)]
+#[description("Toggle between being exluded and included in role distribution. Moderators can mention a player to toggle them instead. Add a duration like `30m` or `2h`, or `session` to be included again automatically after it or at the end of the session.")]
+async fn exclude(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() > 3 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let mut user_id = msg.author.id;
+    let mut duration = None;
+    let mut until_session_end = false;
+    for arg in args.iter::<String>().filter_map(|arg| arg.ok()) {
+        if arg == "session" {
+            until_session_end = true;
+        } else if let Some(parsed) = parse_duration(&arg) {
+            duration = Some(parsed);
+        } else if let Ok(parsed) = arg.parse::<UserId>() {
+            user_id = parsed;
+        } else {
+            return Err(BotError::rejected(format!("Invalid player or duration '{}'.", arg)).into());
         }
+    }
+
+    if user_id != msg.author.id && !is_moderator(ctx, msg).await {
+        return Err(BotError::rejected("Only moderators can exclude other players.").into());
+    }
+    if until_session_end && !load_session(guild_id.0).is_active() {
+        return Err(BotError::rejected("No session is running.").into());
+    }
+
+    let who = match user_id == msg.author.id {
+        true => "You".to_owned(),
+        false => format!("<@{}>", user_id),
+    };
+    let until = duration.map(|duration| unix_now() + duration.as_secs());
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let _lock = lock_guild_exclusions(guild_id.0).await;
+    let mut state = ExclusionState::load(guild_id);
+    let content = toggle_exclusion(&guild, &mut state, user_id, &who, until, until_session_end).await?;
+    state.save(guild_id);
+
+    guild.say_quietly(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Excludes a player, or includes them if they were excluded without any end.
+async fn toggle_exclusion(
+    guild: &impl GuildAccess,
+    state: &mut ExclusionState,
+    user_id: UserId,
+    who: &str,
+    until: Option<u64>,
+    until_session_end: bool,
+) -> Reply {
+    let roles = match guild.member_roles(user_id).await {
+        Some(roles) => roles,
+        None => return Err(BotError::rejected("Player isn't a member of this server.")),
+    };
+    let has_role = match state.role {
+        Some(role_id) => roles.contains(&RoleId(role_id)),
+        None => false,
     };
 
-    let has_role = msg
-        .author
-        .has_role(&ctx.http, guild_id, role_id)
-        .await
-        .unwrap();
-    let mut member = msg.member(&ctx.http).await.unwrap();
-
-    match has_role {
-        true => {
-            member.remove_role(&ctx.http, role_id).await.ok();
-            msg.channel_id
-                .say(
-                    &ctx.http,
-                    "You will be included in role distribution.".to_owned(),
-                )
-                .await
-                .ok();
-        }
-        false => {
-            member.add_role(&ctx.http, role_id).await.ok();
-            msg.channel_id
-                .say(
-                    &ctx.http,
-                    "You won't be included in role distribution.".to_owned(),
-                )
-                .await
-                .ok();
+    let content = match (has_role, until, until_session_end) {
+        (true, None, false) => {
+            include_player(guild, state, user_id).await?;
+            format!("{} will be included in role distribution.", who)
+        }
+        (_, Some(until), _) => {
+            exclude_player(guild, state, user_id, Some(until), until_session_end).await?;
+            format!(
+                "{} won't be included in role distribution until <t:{}:t>.",
+                who, until
+            )
+        }
+        (_, None, true) => {
+            exclude_player(guild, state, user_id, None, true).await?;
+            format!(
+                "{} won't be included in role distribution until the session ends.",
+                who
+            )
         }
+        (false, None, false) => {
+            exclude_player(guild, state, user_id, None, false).await?;
+            format!("{} won't be included in role distribution.", who)
+        }
+    };
+    Ok(content)
+}
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(list)]
+#[description("Management of excluded players.")]
+async fn excluded(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    msg.channel_id
+        .say(ctx, "Invalid subcommand.".to_owned())
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[aliases("l")]
+#[only_in(guilds)]
+#[description("List all currently excluded players and until when.")]
+async fn list(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    if !args.is_empty() {
+        return Err(BotError::rejected("Invalid amount of arguments").into());
+    }
+
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let mut exclusions: Vec<(u64, Exclusion)> = load_guild_exclusions(guild_id.0).into_iter().collect();
+    if exclusions.is_empty() {
+        msg.channel_id.say(ctx, "No excluded players.").await?;
+        return Ok(());
     }
+    exclusions.sort_by_key(|(_user_id, exclusion)| exclusion.until.unwrap_or(u64::MAX));
+
+    let mut content = String::new();
+    content.write_str("Excluded players:\n").ok();
+    for (user_id, exclusion) in exclusions {
+        match (exclusion.until, exclusion.until_session_end) {
+            (Some(until), _) => content
+                .write_fmt(format_args!("- <@{}> until <t:{}:f>,\n", user_id, until))
+                .ok(),
+            (None, true) => content
+                .write_fmt(format_args!("- <@{}> until the session ends,\n", user_id))
+                .ok(),
+            (None, false) => content
+                .write_fmt(format_args!("- <@{}> until included again,\n", user_id))
+                .ok(),
+        };
+    }
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(content).allowed_mentions(|am| am.empty_parse())
+        })
+        .await?;
 
     Ok(())
 }
+
+/// Returns whether the caller can manage other players' exclusions.
+async fn is_moderator(ctx: &Context, msg: &Message) -> bool {
+    let can_manage_roles = match msg.member(ctx).await {
+        Ok(member) => member
+            .permissions(ctx)
+            .await
+            .map(|permissions| permissions.manage_roles())
+            .unwrap_or(false),
+        Err(_) => false,
+    };
+    can_manage_roles || is_manager(ctx, msg).await
+}
+
+/// Exclusion role and exclusions of a guild's players.
+/// Only load it while holding the guild's exclusion lock.
+struct ExclusionState {
+    role: Option<u64>,
+    exclusions: GuildExclusions,
+}
+
+impl ExclusionState {
+    fn load(guild_id: GuildId) -> Self {
+        Self {
+            role: load_guild_config(guild_id.0).excluded_role,
+            exclusions: load_guild_exclusions(guild_id.0),
+        }
+    }
+
+    fn save(self, guild_id: GuildId) {
+        if let Some(role) = self.role {
+            update_guild_config(guild_id.0, |guild_config| guild_config.excluded_role = Some(role));
+        }
+        save_guild_exclusions(guild_id.0, self.exclusions);
+    }
+}
+
+/// Returns the exclusion role, creating it if necessary.
+async fn excluded_role(
+    guild: &impl GuildAccess,
+    state: &mut ExclusionState,
+) -> Result<RoleId, BotError> {
+    let role_id = match state.role {
+        Some(role_id) => RoleId(role_id),
+        None => guild.create_role("Excluded").await?,
+    };
+    state.role = Some(role_id.0);
+    Ok(role_id)
+}
+
+/// Excludes a player, optionally until a unix timestamp or the end of the session.
+async fn exclude_player(
+    guild: &impl GuildAccess,
+    state: &mut ExclusionState,
+    user_id: UserId,
+    until: Option<u64>,
+    until_session_end: bool,
+) -> Result<(), BotError> {
+    let role_id = excluded_role(guild, state).await?;
+    guild.add_member_role(user_id, role_id).await?;
+    state.exclusions.insert(
+        user_id.0,
+        Exclusion {
+            until,
+            until_session_end,
+        },
+    );
+    Ok(())
+}
+
+/// Includes a previously excluded player.
+async fn include_player(
+    guild: &impl GuildAccess,
+    state: &mut ExclusionState,
+    user_id: UserId,
+) -> Result<(), BotError> {
+    let role_id = excluded_role(guild, state).await?;
+    guild.remove_member_role(user_id, role_id).await?;
+    state.exclusions.remove(&user_id.0);
+    Ok(())
+}
+
+/// Includes all players whose exclusion has run out,
+/// or who wait for the end of a session which isn't running.
+pub async fn release_expired_exclusions(ctx: &Context) {
+    let now = unix_now();
+    for (guild_id, guild_exclusions) in load_exclusions() {
+        let session_running = load_session(guild_id).is_active();
+        let is_over = |exclusion: &Exclusion| {
+            matches!(exclusion.until, Some(until) if until <= now)
+                || (exclusion.until_session_end && !session_running)
+        };
+        if !guild_exclusions.values().any(is_over) {
+            continue;
+        }
+        let guild = DiscordGuild::new(ctx, GuildId(guild_id));
+        let _lock = lock_guild_exclusions(guild_id).await;
+        // Reloaded as exclusions may have changed until the lock was free
+        let mut state = ExclusionState::load(guild.id());
+        release_players(&guild, &mut state, is_over).await;
+        state.save(guild.id());
+    }
+}
+
+/// Includes all players excluded until the end of the session.
+pub async fn release_session_exclusions(guild: &impl GuildAccess) {
+    let _lock = lock_guild_exclusions(guild.id().0).await;
+    let mut state = ExclusionState::load(guild.id());
+    release_session_exclusions_in(guild, &mut state).await;
+    state.save(guild.id());
+}
+
+async fn release_session_exclusions_in(guild: &impl GuildAccess, state: &mut ExclusionState) {
+    release_players(guild, state, |exclusion| exclusion.until_session_end).await;
+}
+
+/// Includes every player whose exclusion matches.
+async fn release_players(
+    guild: &impl GuildAccess,
+    state: &mut ExclusionState,
+    released: impl Fn(&Exclusion) -> bool,
+) {
+    let user_ids: Vec<u64> = state
+        .exclusions
+        .iter()
+        .filter(|(_user_id, exclusion)| released(exclusion))
+        .map(|(user_id, _exclusion)| *user_id)
+        .collect();
+    for user_id in user_ids {
+        release_player(guild, state, UserId(user_id)).await;
+    }
+}
+
+/// Includes a player without a caller to report to, the exclusion ends even if the role can't be removed.
+async fn release_player(guild: &impl GuildAccess, state: &mut ExclusionState, user_id: UserId) {
+    if let Err(why) = include_player(guild, state, user_id).await {
+        tracing::warn!(user = user_id.0, error = %why, "failed to include player");
+        state.exclusions.remove(&user_id.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::FakeGuild;
+
+    fn empty_state() -> ExclusionState {
+        ExclusionState {
+            role: None,
+            exclusions: GuildExclusions::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn toggles_exclusion() {
+        let guild = FakeGuild::default();
+        let player = guild.add_member("Player", &[]);
+        let mut state = empty_state();
+
+        let reply = toggle_exclusion(&guild, &mut state, player, "You", None, false).await;
+        assert_eq!(reply.unwrap(), "You won't be included in role distribution.");
+        let excluded = guild.role_id("Excluded").unwrap();
+        assert!(guild.has_role(player, excluded));
+        assert!(state.exclusions.contains_key(&player.0));
+
+        let reply = toggle_exclusion(&guild, &mut state, player, "You", None, false).await;
+        assert_eq!(reply.unwrap(), "You will be included in role distribution.");
+        assert!(!guild.has_role(player, excluded));
+        assert!(state.exclusions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn excludes_until_timestamp() {
+        let guild = FakeGuild::default();
+        let player = guild.add_member("Player", &[]);
+        let mut state = empty_state();
+
+        let reply = toggle_exclusion(&guild, &mut state, player, "You", Some(100), false).await;
+
+        assert_eq!(reply.unwrap(), "You won't be included in role distribution until <t:100:t>.");
+        assert_eq!(state.exclusions[&player.0].until, Some(100));
+    }
+
+    #[tokio::test]
+    async fn rejects_non_members() {
+        let guild = FakeGuild::default();
+        let mut state = empty_state();
+
+        let reply = toggle_exclusion(&guild, &mut state, UserId(42), "You", None, false).await;
+
+        assert!(reply.is_err());
+        assert!(state.exclusions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reports_deleted_exclusion_role() {
+        let guild = FakeGuild::default();
+        let player = guild.add_member("Player", &[]);
+        let mut state = empty_state();
+        state.role = Some(42);
+
+        let reply = toggle_exclusion(&guild, &mut state, player, "You", None, false).await;
+
+        assert!(matches!(reply, Err(BotError::MissingRole(RoleId(42)))));
+        assert!(state.exclusions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn session_end_releases_session_exclusions() {
+        let guild = FakeGuild::default();
+        let resting = guild.add_member("Resting", &[]);
+        let away = guild.add_member("Away", &[]);
+        let mut state = empty_state();
+        exclude_player(&guild, &mut state, resting, None, true).await.unwrap();
+        exclude_player(&guild, &mut state, away, None, false).await.unwrap();
+
+        release_session_exclusions_in(&guild, &mut state).await;
+
+        let excluded = guild.role_id("Excluded").unwrap();
+        assert!(!guild.has_role(resting, excluded));
+        assert!(guild.has_role(away, excluded));
+        assert!(!state.exclusions.contains_key(&resting.0));
+        assert!(state.exclusions.contains_key(&away.0));
+    }
+}
@@ -0,0 +1,263 @@
+//! In-memory guild, jobs and LiveSplit Server for testing commands.
+
+use serenity::{async_trait, model::prelude::*};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+use super::guild::GuildAccess;
+use crate::{
+    error::BotError,
+    model::{Job, Jobs},
+};
+
+/// Job with the given stages and nothing else configured.
+pub fn job(stages: &[(u16, u16)]) -> Job {
+    Job {
+        points: stages.iter().copied().collect(),
+        instructions: None,
+        session_role: None,
+        voice_channel: None,
+        priority: 1,
+        tiers: Vec::new(),
+    }
+}
+
+/// Serves a single LiveSplit connection, answering each received command from the list in order
+/// and hanging up on the next one. Returns the address to connect to.
+pub async fn livesplit_server(answers: &'static [(&'static str, &'static str)]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        for (command, answer) in answers {
+            let line = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(line, *command);
+            writer.write_all(format!("{}\r\n", answer).as_bytes()).await.unwrap();
+        }
+        lines.next_line().await.ok();
+    });
+    address
+}
+
+/// Guild with a "Runner" job with the given stages.
+pub fn runner_guild(stages: &[(u16, u16)]) -> (FakeGuild, Jobs, RoleId) {
+    let guild = FakeGuild::default();
+    let runner = guild.add_role("Runner");
+    let mut jobs = Jobs::new();
+    jobs.insert(runner.0, job(stages));
+    (guild, jobs, runner)
+}
+
+#[derive(Default)]
+pub struct FakeGuild {
+    state: Mutex<FakeState>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    last_id: u64,
+    roles: HashMap<RoleId, String>,
+    voice_channels: HashMap<ChannelId, String>,
+    members: HashMap<UserId, (String, HashSet<RoleId>)>,
+    voice_states: HashMap<UserId, ChannelId>,
+    messages: Vec<(ChannelId, String)>,
+    direct_messages: Vec<(UserId, String)>,
+}
+
+impl FakeState {
+    fn next_id(&mut self) -> u64 {
+        self.last_id += 1;
+        self.last_id
+    }
+}
+
+impl FakeGuild {
+    pub fn add_role(&self, name: &str) -> RoleId {
+        let mut state = self.state.lock().unwrap();
+        let role_id = RoleId(state.next_id());
+        state.roles.insert(role_id, name.to_owned());
+        role_id
+    }
+
+    pub fn add_voice_channel(&self, name: &str) -> ChannelId {
+        let mut state = self.state.lock().unwrap();
+        let channel_id = ChannelId(state.next_id());
+        state.voice_channels.insert(channel_id, name.to_owned());
+        channel_id
+    }
+
+    pub fn add_member(&self, name: &str, roles: &[RoleId]) -> UserId {
+        let mut state = self.state.lock().unwrap();
+        let user_id = UserId(state.next_id());
+        let roles = roles.iter().copied().collect();
+        state.members.insert(user_id, (name.to_owned(), roles));
+        user_id
+    }
+
+    pub fn join_voice(&self, user_id: UserId, channel_id: ChannelId) {
+        let mut state = self.state.lock().unwrap();
+        state.voice_states.insert(user_id, channel_id);
+    }
+
+    pub fn role_id(&self, name: &str) -> Option<RoleId> {
+        let state = self.state.lock().unwrap();
+        state
+            .roles
+            .iter()
+            .find(|(_role_id, role_name)| *role_name == name)
+            .map(|(role_id, _role_name)| *role_id)
+    }
+
+    pub fn has_role(&self, user_id: UserId, role_id: RoleId) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .members
+            .get(&user_id)
+            .map(|(_name, roles)| roles.contains(&role_id))
+            .unwrap_or(false)
+    }
+
+    /// Messages sent to a channel, oldest first.
+    pub fn messages(&self, channel_id: ChannelId) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .messages
+            .iter()
+            .filter(|(message_channel_id, _content)| *message_channel_id == channel_id)
+            .map(|(_message_channel_id, content)| content.clone())
+            .collect()
+    }
+
+    /// Direct messages sent to a member, oldest first.
+    pub fn direct_messages(&self, user_id: UserId) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .direct_messages
+            .iter()
+            .filter(|(recipient, _content)| *recipient == user_id)
+            .map(|(_recipient, content)| content.clone())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl GuildAccess for FakeGuild {
+    fn id(&self) -> GuildId {
+        GuildId(0)
+    }
+
+    async fn name(&self) -> Option<String> {
+        None
+    }
+
+    async fn voice_channel(&self, user_id: UserId) -> Option<ChannelId> {
+        self.state.lock().unwrap().voice_states.get(&user_id).copied()
+    }
+
+    async fn voice_members(&self, channel_id: ChannelId) -> Vec<UserId> {
+        let state = self.state.lock().unwrap();
+        state
+            .voice_states
+            .iter()
+            .filter(|(_user_id, voice_channel_id)| **voice_channel_id == channel_id)
+            .map(|(user_id, _voice_channel_id)| *user_id)
+            .collect()
+    }
+
+    async fn member_roles(&self, user_id: UserId) -> Option<HashSet<RoleId>> {
+        let state = self.state.lock().unwrap();
+        state.members.get(&user_id).map(|(_name, roles)| roles.clone())
+    }
+
+    async fn display_name(&self, user_id: UserId) -> String {
+        let state = self.state.lock().unwrap();
+        match state.members.get(&user_id) {
+            Some((name, _roles)) => name.clone(),
+            None => user_id.to_string(),
+        }
+    }
+
+    async fn roles(&self) -> Result<HashMap<RoleId, String>, BotError> {
+        Ok(self.state.lock().unwrap().roles.clone())
+    }
+
+    async fn voice_channels(&self) -> Result<HashMap<ChannelId, String>, BotError> {
+        Ok(self.state.lock().unwrap().voice_channels.clone())
+    }
+
+    async fn create_role(&self, name: &str) -> Result<RoleId, BotError> {
+        Ok(self.add_role(name))
+    }
+
+    async fn delete_role(&self, role_id: RoleId) -> Result<(), BotError> {
+        let mut state = self.state.lock().unwrap();
+        state.members.values_mut().for_each(|(_name, roles)| {
+            roles.remove(&role_id);
+        });
+        match state.roles.remove(&role_id) {
+            Some(_) => Ok(()),
+            None => Err(BotError::MissingRole(role_id)),
+        }
+    }
+
+    async fn add_member_role(&self, user_id: UserId, role_id: RoleId) -> Result<(), BotError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.roles.contains_key(&role_id) {
+            return Err(BotError::MissingRole(role_id));
+        }
+        if let Some((_name, roles)) = state.members.get_mut(&user_id) {
+            roles.insert(role_id);
+        }
+        Ok(())
+    }
+
+    async fn remove_member_role(&self, user_id: UserId, role_id: RoleId) -> Result<(), BotError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.roles.contains_key(&role_id) {
+            return Err(BotError::MissingRole(role_id));
+        }
+        if let Some((_name, roles)) = state.members.get_mut(&user_id) {
+            roles.remove(&role_id);
+        }
+        Ok(())
+    }
+
+    // Discord only moves members who are connected to voice.
+    async fn move_member(&self, user_id: UserId, channel_id: ChannelId) -> Result<(), BotError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.voice_channels.contains_key(&channel_id) {
+            return Err(serenity::Error::Other("Unknown channel").into());
+        }
+        match state.voice_states.get_mut(&user_id) {
+            Some(voice_channel_id) => {
+                *voice_channel_id = channel_id;
+                Ok(())
+            }
+            None => Err(serenity::Error::Other("Target user is not connected to voice").into()),
+        }
+    }
+
+    async fn direct_message(&self, user_id: UserId, content: &str) -> Result<(), BotError> {
+        let mut state = self.state.lock().unwrap();
+        state.direct_messages.push((user_id, content.to_owned()));
+        Ok(())
+    }
+
+    async fn say(&self, channel_id: ChannelId, content: &str) -> Result<(), BotError> {
+        let mut state = self.state.lock().unwrap();
+        state.messages.push((channel_id, content.to_owned()));
+        Ok(())
+    }
+
+    async fn say_quietly(&self, channel_id: ChannelId, content: &str) -> Result<(), BotError> {
+        self.say(channel_id, content).await
+    }
+}
@@ -0,0 +1,310 @@
+//! Guild operations the bot needs, so commands don't depend on Discord directly.
+
+use serenity::{async_trait, cache::Cache, http::Http, model::prelude::*, prelude::*, CacheAndHttp};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::error::BotError;
+
+/// Access to a single guild.
+#[async_trait]
+pub trait GuildAccess: Send + Sync {
+    fn id(&self) -> GuildId;
+    /// Name of the guild, if it's known.
+    async fn name(&self) -> Option<String>;
+    /// Voice channel a user is connected to.
+    async fn voice_channel(&self, user_id: UserId) -> Option<ChannelId>;
+    /// Users connected to a voice channel, excluding bots.
+    async fn voice_members(&self, channel_id: ChannelId) -> Vec<UserId>;
+    /// Roles of a member, nothing if they aren't in the guild.
+    async fn member_roles(&self, user_id: UserId) -> Option<HashSet<RoleId>>;
+    /// Roles of several members, skipping ones who aren't in the guild.
+    async fn members_roles(&self, user_ids: &[UserId]) -> HashMap<UserId, HashSet<RoleId>> {
+        let mut users_roles = HashMap::new();
+        for user_id in user_ids {
+            if let Some(roles) = self.member_roles(*user_id).await {
+                users_roles.insert(*user_id, roles);
+            }
+        }
+        users_roles
+    }
+    /// Name of a member as displayed in the guild.
+    async fn display_name(&self, user_id: UserId) -> String;
+    /// Names of all roles.
+    async fn roles(&self) -> Result<HashMap<RoleId, String>, BotError>;
+    /// Names of all voice channels.
+    async fn voice_channels(&self) -> Result<HashMap<ChannelId, String>, BotError>;
+    async fn create_role(&self, name: &str) -> Result<RoleId, BotError>;
+    async fn delete_role(&self, role_id: RoleId) -> Result<(), BotError>;
+    async fn add_member_role(&self, user_id: UserId, role_id: RoleId) -> Result<(), BotError>;
+    async fn remove_member_role(&self, user_id: UserId, role_id: RoleId) -> Result<(), BotError>;
+    /// Moves a member connected to voice into another voice channel.
+    async fn move_member(&self, user_id: UserId, channel_id: ChannelId) -> Result<(), BotError>;
+    /// Sends a direct message to a member.
+    async fn direct_message(&self, user_id: UserId, content: &str) -> Result<(), BotError>;
+    /// Sends a message, mentions in it ping.
+    async fn say(&self, channel_id: ChannelId, content: &str) -> Result<(), BotError>;
+    /// Sends a message without pinging anyone mentioned in it.
+    async fn say_quietly(&self, channel_id: ChannelId, content: &str) -> Result<(), BotError>;
+}
+
+/// Guild accessed through Discord.
+pub struct DiscordGuild<'a> {
+    cache: &'a Arc<Cache>,
+    http: &'a Http,
+    guild_id: GuildId,
+}
+
+impl<'a> DiscordGuild<'a> {
+    pub fn new(ctx: &'a Context, guild_id: GuildId) -> Self {
+        Self {
+            cache: &ctx.cache,
+            http: &ctx.http,
+            guild_id,
+        }
+    }
+
+    /// Guild accessed without a context, like while shutting down.
+    pub fn from_cache_and_http(cache_and_http: &'a CacheAndHttp, guild_id: GuildId) -> Self {
+        Self {
+            cache: &cache_and_http.cache,
+            http: &cache_and_http.http,
+            guild_id,
+        }
+    }
+
+    /// Voice channel members fetched over HTTP, for when the guild isn't cached.
+    async fn fetch_voice_members(&self, channel_id: ChannelId) -> Vec<UserId> {
+        let channel = match channel_id.to_channel((self.cache, self.http)).await {
+            Ok(channel) => channel.guild(),
+            Err(_) => None,
+        };
+        let members = match channel {
+            Some(channel) => channel.members(self.cache).await.unwrap_or_default(),
+            None => Vec::new(),
+        };
+        members
+            .into_iter()
+            .filter(|member| !member.user.bot)
+            .map(|member| member.user.id)
+            .collect()
+    }
+
+    /// Roles of members missing from the cache.
+    /// Lists the guild's members in pages unless asking for each member takes fewer requests.
+    async fn fetch_members_roles(
+        &self,
+        mut missing: HashSet<UserId>,
+    ) -> HashMap<UserId, HashSet<RoleId>> {
+        let mut users_roles = HashMap::new();
+        let pages = self
+            .cache
+            .guild_field(self.guild_id, |guild| guild.member_count / MEMBERS_PAGE + 1)
+            .await
+            .unwrap_or(1);
+        if missing.len() as u64 <= pages {
+            for user_id in missing {
+                if let Some(roles) = self.member_roles(user_id).await {
+                    users_roles.insert(user_id, roles);
+                }
+            }
+            return users_roles;
+        }
+
+        let mut after = None;
+        while !missing.is_empty() {
+            let page = match self.guild_id.members(self.http, Some(MEMBERS_PAGE), after).await {
+                Ok(page) => page,
+                Err(why) => {
+                    tracing::warn!(guild = self.guild_id.0, error = %why, "failed to list members");
+                    break;
+                }
+            };
+            after = page.last().map(|member| member.user.id);
+            let last_page = (page.len() as u64) < MEMBERS_PAGE;
+            for member in page {
+                if missing.remove(&member.user.id) {
+                    users_roles.insert(member.user.id, member.roles.into_iter().collect());
+                }
+            }
+            if last_page {
+                break;
+            }
+        }
+        users_roles
+    }
+}
+
+/// Most members Discord lists in one request.
+const MEMBERS_PAGE: u64 = 1000;
+
+#[async_trait]
+impl GuildAccess for DiscordGuild<'_> {
+    fn id(&self) -> GuildId {
+        self.guild_id
+    }
+
+    async fn name(&self) -> Option<String> {
+        self.guild_id.name(self.cache).await
+    }
+
+    async fn voice_channel(&self, user_id: UserId) -> Option<ChannelId> {
+        self.cache
+            .guild_field(self.guild_id, |guild| {
+                guild
+                    .voice_states
+                    .get(&user_id)
+                    .and_then(|voice_state| voice_state.channel_id)
+            })
+            .await?
+    }
+
+    async fn voice_members(&self, channel_id: ChannelId) -> Vec<UserId> {
+        let cached = self
+            .cache
+            .guild_field(self.guild_id, |guild| {
+                guild
+                    .voice_states
+                    .values()
+                    .filter(|voice_state| voice_state.channel_id == Some(channel_id))
+                    .filter(|voice_state| {
+                        let is_bot = guild
+                            .members
+                            .get(&voice_state.user_id)
+                            .or(voice_state.member.as_ref())
+                            .map(|member| member.user.bot);
+                        is_bot != Some(true)
+                    })
+                    .map(|voice_state| voice_state.user_id)
+                    .collect()
+            })
+            .await;
+        match cached {
+            Some(user_ids) => user_ids,
+            None => self.fetch_voice_members(channel_id).await,
+        }
+    }
+
+    async fn member_roles(&self, user_id: UserId) -> Option<HashSet<RoleId>> {
+        let member = self.guild_id.member((self.cache, self.http), user_id).await.ok()?;
+        Some(member.roles.into_iter().collect())
+    }
+
+    async fn members_roles(&self, user_ids: &[UserId]) -> HashMap<UserId, HashSet<RoleId>> {
+        let mut users_roles: HashMap<UserId, HashSet<RoleId>> = self
+            .cache
+            .guild_field(self.guild_id, |guild| {
+                user_ids
+                    .iter()
+                    .filter_map(|user_id| guild.members.get(user_id))
+                    .map(|member| (member.user.id, member.roles.iter().copied().collect()))
+                    .collect()
+            })
+            .await
+            .unwrap_or_default();
+        let missing: HashSet<UserId> = user_ids
+            .iter()
+            .filter(|user_id| !users_roles.contains_key(user_id))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            users_roles.extend(self.fetch_members_roles(missing).await);
+        }
+        users_roles
+    }
+
+    async fn display_name(&self, user_id: UserId) -> String {
+        match self.guild_id.member((self.cache, self.http), user_id).await {
+            Ok(member) => member.display_name().into_owned(),
+            Err(_) => user_id.to_string(),
+        }
+    }
+
+    async fn roles(&self) -> Result<HashMap<RoleId, String>, BotError> {
+        let roles = self.guild_id.roles(self.http).await?;
+        Ok(roles
+            .into_iter()
+            .map(|(role_id, role)| (role_id, role.name))
+            .collect())
+    }
+
+    async fn voice_channels(&self) -> Result<HashMap<ChannelId, String>, BotError> {
+        let channels = self.guild_id.channels(self.http).await?;
+        Ok(channels
+            .into_iter()
+            .filter(|(_channel_id, channel)| channel.kind == ChannelType::Voice)
+            .map(|(channel_id, channel)| (channel_id, channel.name))
+            .collect())
+    }
+
+    async fn create_role(&self, name: &str) -> Result<RoleId, BotError> {
+        let role = self
+            .guild_id
+            .create_role(self.http, |r| r.name(name))
+            .await?;
+        Ok(role.id)
+    }
+
+    async fn delete_role(&self, role_id: RoleId) -> Result<(), BotError> {
+        Ok(self.guild_id.delete_role(self.http, role_id).await?)
+    }
+
+    async fn add_member_role(&self, user_id: UserId, role_id: RoleId) -> Result<(), BotError> {
+        self.http
+            .add_member_role(self.guild_id.0, user_id.0, role_id.0)
+            .await
+            .map_err(|why| missing_role(why, role_id))
+    }
+
+    async fn remove_member_role(&self, user_id: UserId, role_id: RoleId) -> Result<(), BotError> {
+        self.http
+            .remove_member_role(self.guild_id.0, user_id.0, role_id.0)
+            .await
+            .map_err(|why| missing_role(why, role_id))
+    }
+
+    async fn move_member(&self, user_id: UserId, channel_id: ChannelId) -> Result<(), BotError> {
+        self.guild_id.move_member(self.http, user_id, channel_id).await?;
+        Ok(())
+    }
+
+    async fn direct_message(&self, user_id: UserId, content: &str) -> Result<(), BotError> {
+        let channel = user_id.create_dm_channel(self.http).await?;
+        channel.say(self.http, content).await?;
+        Ok(())
+    }
+
+    async fn say(&self, channel_id: ChannelId, content: &str) -> Result<(), BotError> {
+        channel_id.say(self.http, content).await?;
+        Ok(())
+    }
+
+    async fn say_quietly(&self, channel_id: ChannelId, content: &str) -> Result<(), BotError> {
+        channel_id
+            .send_message(self.http, |m| {
+                m.content(content).allowed_mentions(|am| am.empty_parse())
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+/// Tells apart roles which no longer exist from other failures.
+fn missing_role(why: serenity::Error, role_id: RoleId) -> BotError {
+    match &why {
+        serenity::Error::Http(http_error) => match http_error.as_ref() {
+            serenity::http::HttpError::UnsuccessfulRequest(response)
+                if response.error.code == UNKNOWN_ROLE =>
+            {
+                BotError::MissingRole(role_id)
+            }
+            _ => why.into(),
+        },
+        _ => why.into(),
+    }
+}
+
+/// Discord's JSON error code for requests referencing a deleted role.
+const UNKNOWN_ROLE: isize = 10011;
@@ -0,0 +1,329 @@
+use crate::{
+    error::BotError,
+    livesplit::{LiveSplitClient, SplitWatcher, TimerEvent},
+    model::{load_guild_config, update_guild_config, LiveSplitConfig},
+};
+use serenity::{
+    framework::standard::{macros::command, Args, CommandResult},
+    model::prelude::*,
+    prelude::*,
+};
+use std::{collections::HashMap, fmt::Write, io, sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+use super::{
+    checks::MANAGER_CHECK,
+    guild::{DiscordGuild, GuildAccess},
+    roll::{assign_roles, RollTarget},
+    schedule::RollTask,
+    util::parse_switch,
+};
+
+/// How often the timer is polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Running LiveSplit Server connections by guild.
+pub struct LiveSplitConnections;
+
+impl TypeMapKey for LiveSplitConnections {
+    type Value = Arc<Mutex<HashMap<GuildId, LiveSplitConnection>>>;
+}
+
+/// Watcher of a LiveSplit Server with the configuration it rolls by,
+/// so changing the configuration doesn't need a reconnect and polls don't reread it.
+pub struct LiveSplitConnection {
+    task: RollTask,
+    livesplit: Arc<RwLock<LiveSplitConfig>>,
+}
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(connect, disconnect, splits, reset)]
+#[description("Roll automatically on splits and resets of a LiveSplit Server. Shows the current configuration.")]
+async fn livesplit(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    if !args.is_empty() {
+        return Err(BotError::rejected("Invalid subcommand.").into());
+    }
+
+    let guild_config = load_guild_config(msg.guild_id.ok_or(BotError::NotInGuild)?.0);
+    let livesplit = guild_config.livesplit;
+    let mut content = String::new();
+    content.write_str("LiveSplit:\n").ok();
+    content
+        .write_fmt(format_args!(
+            "**Server**: {}\n",
+            livesplit.address.as_deref().unwrap_or("none")
+        ))
+        .ok();
+    match livesplit.splits.is_empty() {
+        true => content.write_str("**Splits**: all\n").ok(),
+        false => content
+            .write_fmt(format_args!("**Splits**: {}\n", livesplit.splits.join(", ")))
+            .ok(),
+    };
+    content
+        .write_fmt(format_args!(
+            "**Roll on reset**: {}\n",
+            if livesplit.roll_on_reset { "on" } else { "off" }
+        ))
+        .ok();
+
+    msg.channel_id.say(ctx, content).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Connect to a LiveSplit Server and roll for the caller's voice channel on configured splits. Specify the server as `host:port`, or leave it empty to use the last one.")]
+async fn connect(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() > 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild_config = load_guild_config(guild_id.0);
+    let address = match args.single::<String>().ok().or(guild_config.livesplit.address) {
+        Some(address) => address,
+        None => {
+            return Err(BotError::rejected("No server specified.").into());
+        }
+    };
+    let target = RollTarget::from_message(ctx, msg).await?;
+    if target.voice_channel_id.is_none() {
+        return Err(BotError::NotInVoiceChannel.into());
+    }
+
+    let client = match LiveSplitClient::connect(&address).await {
+        Ok(client) => client,
+        Err(why) => {
+            return Err(BotError::rejected(format!("Failed to connect to '{}': {}.", address, why)).into());
+        }
+    };
+    let livesplit = update_guild_config(guild_id.0, |guild_config| {
+        guild_config.livesplit.address = Some(address.clone());
+        guild_config.livesplit.clone()
+    });
+    let livesplit = Arc::new(RwLock::new(livesplit));
+
+    let task_ctx = ctx.clone();
+    let task_livesplit = livesplit.clone();
+    let task = RollTask::spawn(move |stop| watch_timer(task_ctx, client, target, task_livesplit, stop));
+    let connections = livesplit_connections(ctx).await;
+    let previous = connections.lock().await.insert(guild_id, LiveSplitConnection { task, livesplit });
+    if let Some(previous) = previous {
+        previous.task.stop().await;
+    }
+
+    msg.channel_id
+        .say(ctx, format!("Connected to '{}'.", address))
+        .await?;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Disconnect from the LiveSplit Server.")]
+async fn disconnect(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let connections = livesplit_connections(ctx).await;
+    let removed = connections.lock().await.remove(&msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let content = match removed {
+        Some(connection) => {
+            connection.task.stop().await;
+            "Disconnected."
+        }
+        None => "Not connected.",
+    };
+
+    msg.channel_id.say(ctx, content.to_owned()).await?;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Set the names of splits which trigger a roll. Leave empty to roll on every split.")]
+async fn splits(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    args.trimmed().quoted();
+    let names: Vec<String> = args.iter::<String>().filter_map(|name| name.ok()).collect();
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let content = match names.is_empty() {
+        true => "Rolling on every split.".to_owned(),
+        false => format!("Rolling on splits {}.", names.join(", ")),
+    };
+    let livesplit = update_guild_config(guild_id.0, |guild_config| {
+        guild_config.livesplit.splits = names;
+        guild_config.livesplit.clone()
+    });
+    refresh_connection(ctx, guild_id, livesplit).await;
+
+    msg.channel_id.say(ctx, content).await?;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Turn `on` or `off` rolling when the run is reset.")]
+async fn reset(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() != 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let enabled = parse_switch(&args.single::<String>().unwrap())?;
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let livesplit = update_guild_config(guild_id.0, |guild_config| {
+        guild_config.livesplit.roll_on_reset = enabled;
+        guild_config.livesplit.clone()
+    });
+    refresh_connection(ctx, guild_id, livesplit).await;
+
+    let content = match enabled {
+        true => "Rolling when the run is reset.",
+        false => "Not rolling when the run is reset.",
+    };
+    msg.channel_id.say(ctx, content.to_owned()).await?;
+    Ok(())
+}
+
+/// Hands a changed configuration to the running connection of a guild.
+async fn refresh_connection(ctx: &Context, guild_id: GuildId, livesplit: LiveSplitConfig) {
+    let connections = livesplit_connections(ctx).await;
+    let connections = connections.lock().await;
+    if let Some(connection) = connections.get(&guild_id) {
+        *connection.livesplit.write().await = livesplit;
+    }
+}
+
+/// Polls the timer and rolls on configured splits and resets until the connection is lost
+/// or it's told to stop.
+async fn watch_timer(
+    ctx: Context,
+    mut client: LiveSplitClient,
+    target: RollTarget,
+    livesplit: Arc<RwLock<LiveSplitConfig>>,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut watcher = SplitWatcher::default();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        let polled = tokio::select! {
+            polled = async {
+                interval.tick().await;
+                let livesplit = livesplit.read().await.clone();
+                poll_timer(&mut client, &mut watcher, &livesplit).await
+            } => polled,
+            _ = stop.changed() => return,
+        };
+        match polled {
+            Ok(true) => {
+                if let Err(why) = assign_roles(&ctx, target).await {
+                    tracing::error!(guild = target.guild_id.0, error = %why, "LiveSplit roll failed");
+                }
+            }
+            Ok(false) => {}
+            Err(_) => break,
+        }
+    }
+
+    DiscordGuild::new(&ctx, target.guild_id)
+        .say(target.channel_id, "Lost connection to LiveSplit Server.")
+        .await
+        .ok();
+    livesplit_connections(&ctx)
+        .await
+        .lock()
+        .await
+        .remove(&target.guild_id);
+}
+
+/// Disconnects from all LiveSplit Servers and waits until no watcher is running.
+pub async fn disconnect_all(data: &RwLock<TypeMap>) {
+    let connections = match data.read().await.get::<LiveSplitConnections>().cloned() {
+        Some(connections) => connections,
+        None => return,
+    };
+    let connections: Vec<LiveSplitConnection> =
+        connections.lock().await.drain().map(|(_guild_id, connection)| connection).collect();
+    for connection in connections {
+        connection.task.stop().await;
+    }
+}
+
+/// Polls the timer once, returns whether it reached a configured split or reset.
+async fn poll_timer(
+    client: &mut LiveSplitClient,
+    watcher: &mut SplitWatcher,
+    livesplit: &LiveSplitConfig,
+) -> io::Result<bool> {
+    let index = client.split_index().await?;
+    let should_roll = match watcher.update(index) {
+        Some(TimerEvent::Split) if livesplit.splits.is_empty() => true,
+        Some(TimerEvent::Split) => livesplit.splits.contains(&client.previous_split_name().await?),
+        Some(TimerEvent::Reset) => livesplit.roll_on_reset,
+        None => false,
+    };
+    Ok(should_roll)
+}
+
+async fn livesplit_connections(ctx: &Context) -> Arc<Mutex<HashMap<GuildId, LiveSplitConnection>>> {
+    ctx.data
+        .read()
+        .await
+        .get::<LiveSplitConnections>()
+        .cloned()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::livesplit_server;
+
+    #[tokio::test]
+    async fn rolls_on_configured_splits() {
+        let address = livesplit_server(&[
+            ("getsplitindex", "0"),
+            ("getsplitindex", "1"),
+            ("getprevioussplitname", "Forest"),
+            ("getsplitindex", "2"),
+            ("getprevioussplitname", "Castle"),
+        ])
+        .await;
+        let mut client = LiveSplitClient::connect(&address).await.unwrap();
+        let mut watcher = SplitWatcher::default();
+        let livesplit = LiveSplitConfig {
+            splits: vec!["Castle".to_owned()],
+            ..LiveSplitConfig::default()
+        };
+
+        assert!(!poll_timer(&mut client, &mut watcher, &livesplit).await.unwrap());
+        assert!(!poll_timer(&mut client, &mut watcher, &livesplit).await.unwrap());
+        assert!(poll_timer(&mut client, &mut watcher, &livesplit).await.unwrap());
+        assert!(poll_timer(&mut client, &mut watcher, &livesplit).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rolls_on_reset_when_turned_on() {
+        let address = livesplit_server(&[
+            ("getsplitindex", "1"),
+            ("getsplitindex", "-1"),
+            ("getsplitindex", "0"),
+            ("getsplitindex", "-1"),
+        ])
+        .await;
+        let mut client = LiveSplitClient::connect(&address).await.unwrap();
+        let mut watcher = SplitWatcher::default();
+        let mut livesplit = LiveSplitConfig::default();
+
+        assert!(!poll_timer(&mut client, &mut watcher, &livesplit).await.unwrap());
+        assert!(!poll_timer(&mut client, &mut watcher, &livesplit).await.unwrap());
+        livesplit.roll_on_reset = true;
+        assert!(!poll_timer(&mut client, &mut watcher, &livesplit).await.unwrap());
+        assert!(poll_timer(&mut client, &mut watcher, &livesplit).await.unwrap());
+    }
+}
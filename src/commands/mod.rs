@@ -1,7 +1,17 @@
-mod util;
+mod checks;
+#[cfg(test)]
+pub(crate) mod fake;
+pub(crate) mod guild;
+pub(crate) mod util;
 
 pub mod roles;
 pub mod stages;
 pub mod roll;
 pub mod exclude;
-pub mod simulate;
\ No newline at end of file
+pub mod simulate;
+pub mod settings;
+pub mod session;
+pub mod stats;
+pub mod schedule;
+pub mod livesplit;
+pub mod qualify;
\ No newline at end of file
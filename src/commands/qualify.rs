@@ -0,0 +1,248 @@
+use crate::{
+    error::BotError,
+    model::{
+        load_guild_config, load_guild_qualifications, load_jobs, lock_guild_qualifications,
+        save_guild_qualifications, GuildQualifications, Jobs,
+    },
+};
+use serenity::{
+    framework::standard::{macros::command, Args, CommandResult},
+    model::prelude::*,
+    prelude::*,
+};
+use std::{collections::HashMap, fmt::Write};
+
+use super::{
+    checks::MANAGER_CHECK,
+    guild::{DiscordGuild, GuildAccess},
+    util::{role_by_name, Reply},
+};
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Register a player as qualified for a role without them needing the Discord role. Mention the player, followed by the role name. The Discord role is granted too if mirroring is turned on in the settings.")]
+async fn qualify(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let (user_id, name) = player_and_role(args)?;
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let mirror = load_guild_config(guild_id.0).mirror_qualifications;
+    let _lock = lock_guild_qualifications(guild_id.0).await;
+    let mut qualifications = load_guild_qualifications(guild_id.0);
+    let content =
+        add_qualification(&guild, &load_jobs(), &mut qualifications, mirror, user_id, &name).await?;
+    save_guild_qualifications(guild_id.0, qualifications);
+
+    guild.say_quietly(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Remove a qualification registered with `qualify`. Mention the player, followed by the role name. The Discord role is removed too if mirroring is turned on in the settings.")]
+async fn unqualify(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let (user_id, name) = player_and_role(args)?;
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let mirror = load_guild_config(guild_id.0).mirror_qualifications;
+    let _lock = lock_guild_qualifications(guild_id.0).await;
+    let mut qualifications = load_guild_qualifications(guild_id.0);
+    let content =
+        remove_qualification(&guild, &mut qualifications, mirror, user_id, &name).await?;
+    save_guild_qualifications(guild_id.0, qualifications);
+
+    guild.say_quietly(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("List players registered as qualified for a role with `qualify`.")]
+async fn qualified(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() != 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let name = args.single::<String>().unwrap();
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let content = list_qualified(&guild, &load_guild_qualifications(guild_id.0), &name).await?;
+
+    guild.say_quietly(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Parses a mentioned player followed by a role name.
+fn player_and_role(mut args: Args) -> Result<(UserId, String), BotError> {
+    if args.len() != 2 {
+        return Err(BotError::rejected("Invalid amount of arguments."));
+    }
+
+    args.trimmed().quoted();
+    let user_id = args
+        .single::<UserId>()
+        .map_err(|_| BotError::rejected("Invalid player, mention them."))?;
+    Ok((user_id, args.single::<String>().unwrap()))
+}
+
+/// Returns a role which qualifies for some job, by its own role or a tier.
+fn qualifying_role(
+    roles: &HashMap<RoleId, String>,
+    jobs: &Jobs,
+    name: &str,
+) -> Result<RoleId, BotError> {
+    let role_id = match role_by_name(roles, name) {
+        Some(role_id) => role_id,
+        None => return Err(BotError::rejected("Role doesn't exist.")),
+    };
+    match jobs.contains_key(&role_id.0) || jobs.values().any(|job| job.tiers.contains(&role_id.0)) {
+        true => Ok(role_id),
+        false => Err(BotError::rejected("Role doesn't qualify for any job.")),
+    }
+}
+
+/// Registers a player as qualified, granting the Discord role if mirrored.
+async fn add_qualification(
+    guild: &impl GuildAccess,
+    jobs: &Jobs,
+    qualifications: &mut GuildQualifications,
+    mirror: bool,
+    user_id: UserId,
+    name: &str,
+) -> Reply {
+    if guild.member_roles(user_id).await.is_none() {
+        return Err(BotError::rejected("Player isn't a member of this server."));
+    }
+    let role_id = qualifying_role(&guild.roles().await?, jobs, name)?;
+
+    let registered = qualifications.get(&user_id.0);
+    if registered.is_some_and(|role_ids| role_ids.contains(&role_id.0)) {
+        return Err(BotError::rejected("Player is already qualified for the role."));
+    }
+    if mirror {
+        guild.add_member_role(user_id, role_id).await?;
+    }
+    qualifications.entry(user_id.0).or_default().push(role_id.0);
+    Ok(format!("<@{}> is qualified for '{}'.", user_id, name))
+}
+
+/// Removes a registered qualification, removing the Discord role if mirrored.
+async fn remove_qualification(
+    guild: &impl GuildAccess,
+    qualifications: &mut GuildQualifications,
+    mirror: bool,
+    user_id: UserId,
+    name: &str,
+) -> Reply {
+    let role_id = match role_by_name(&guild.roles().await?, name) {
+        Some(role_id) => role_id,
+        None => return Err(BotError::rejected("Role doesn't exist.")),
+    };
+    let role_ids = qualifications.get_mut(&user_id.0);
+    let position = role_ids
+        .as_ref()
+        .and_then(|role_ids| role_ids.iter().position(|registered| *registered == role_id.0));
+    let (role_ids, position) = match (role_ids, position) {
+        (Some(role_ids), Some(position)) => (role_ids, position),
+        _ => return Err(BotError::rejected("Player isn't registered as qualified for the role.")),
+    };
+
+    if mirror {
+        guild.remove_member_role(user_id, role_id).await?;
+    }
+    role_ids.remove(position);
+    if role_ids.is_empty() {
+        qualifications.remove(&user_id.0);
+    }
+    Ok(format!("<@{}> is no longer registered as qualified for '{}'.", user_id, name))
+}
+
+/// Lists players registered as qualified for a role.
+async fn list_qualified(
+    guild: &impl GuildAccess,
+    qualifications: &GuildQualifications,
+    name: &str,
+) -> Reply {
+    let role_id = match role_by_name(&guild.roles().await?, name) {
+        Some(role_id) => role_id,
+        None => return Err(BotError::rejected("Role doesn't exist.")),
+    };
+    let mut players: Vec<u64> = qualifications
+        .iter()
+        .filter(|(_user_id, role_ids)| role_ids.contains(&role_id.0))
+        .map(|(user_id, _role_ids)| *user_id)
+        .collect();
+    if players.is_empty() {
+        return Ok(format!("No players are registered as qualified for '{}'.", name));
+    }
+    players.sort_unstable();
+
+    let mut content = String::new();
+    content
+        .write_fmt(format_args!("Registered as qualified for '{}':\n", name))
+        .ok();
+    for user_id in players {
+        content.write_fmt(format_args!("- <@{}>,\n", user_id)).ok();
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{fake::runner_guild, util::merge_qualifications};
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn registers_qualifications() {
+        let (guild, jobs, runner) = runner_guild(&[(4, 1)]);
+        let player = guild.add_member("Player", &[]);
+        guild.add_role("Commentator");
+        let mut qualifications = GuildQualifications::new();
+
+        let reply = add_qualification(&guild, &jobs, &mut qualifications, false, player, "Commentator").await;
+        assert_eq!(reply.unwrap_err().to_string(), "Role doesn't qualify for any job.");
+
+        let reply = add_qualification(&guild, &jobs, &mut qualifications, false, player, "Runner").await;
+        assert!(reply.is_ok());
+        assert_eq!(qualifications[&player.0], vec![runner.0]);
+        assert!(!guild.has_role(player, runner));
+
+        let reply = add_qualification(&guild, &jobs, &mut qualifications, false, player, "Runner").await;
+        assert_eq!(reply.unwrap_err().to_string(), "Player is already qualified for the role.");
+
+        let content = list_qualified(&guild, &qualifications, "Runner").await.unwrap();
+        assert_eq!(content, format!("Registered as qualified for 'Runner':\n- <@{}>,\n", player));
+
+        assert!(remove_qualification(&guild, &mut qualifications, false, player, "Runner").await.is_ok());
+        assert!(qualifications.is_empty());
+    }
+
+    #[tokio::test]
+    async fn mirrors_discord_roles() {
+        let (guild, jobs, runner) = runner_guild(&[(4, 1)]);
+        let player = guild.add_member("Player", &[]);
+        let mut qualifications = GuildQualifications::new();
+
+        add_qualification(&guild, &jobs, &mut qualifications, true, player, "Runner").await.unwrap();
+        assert!(guild.has_role(player, runner));
+
+        remove_qualification(&guild, &mut qualifications, true, player, "Runner").await.unwrap();
+        assert!(!guild.has_role(player, runner));
+    }
+
+    #[test]
+    fn merges_registered_qualifications() {
+        let runner = RoleId(1);
+        let mut users_roles: HashMap<UserId, HashSet<RoleId>> =
+            vec![(UserId(2), HashSet::new()), (UserId(3), HashSet::new())].into_iter().collect();
+        let qualifications: GuildQualifications = vec![(2, vec![runner.0])].into_iter().collect();
+
+        merge_qualifications(&mut users_roles, &qualifications);
+
+        assert!(users_roles[&UserId(2)].contains(&runner));
+        assert!(users_roles[&UserId(3)].is_empty());
+    }
+}
@@ -1,5 +1,8 @@
-use crate::model::{load_jobs, save_jobs, Job};
-use indexmap::IndexMap;
+use crate::{
+    error::BotError,
+    model::{load_jobs, save_jobs, Job, Jobs},
+};
+use role_dispatch_core::Stages;
 use serenity::{
     framework::standard::{
         macros::command,
@@ -10,15 +13,20 @@ use serenity::{
 };
 use std::fmt::Write;
 
+use super::{
+    checks::MANAGER_CHECK,
+    guild::{DiscordGuild, GuildAccess},
+    util::{role_by_name, role_name, Reply},
+};
+
 #[command]
-#[sub_commands(add, remove, list)]
+#[sub_commands(add, remove, list, instructions, session_role, channel, priority, tiers)]
 #[only_in(guilds)]
 #[description("Management of roles.")]
 async fn roles(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
     msg.channel_id
         .say(ctx, "Invalid subcommand.".to_owned())
-        .await
-        .ok();
+        .await?;
 
     Ok(())
 }
@@ -26,155 +34,528 @@ async fn roles(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
 #[command]
 #[aliases("+")]
 #[only_in(guilds)]
+#[checks(Manager)]
 #[description("Add a new role by specifying it's name.")]
 async fn add(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     if args.len() != 1 {
-        msg.channel_id
-            .say(ctx, "Invalid amount of arguments.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
     }
 
     args.trimmed().quoted();
     let name = args.single::<String>().unwrap();
-    let partial_guild = msg
-        .guild_id
-        .unwrap()
-        .to_partial_guild(&ctx.http)
-        .await
-        .unwrap();
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let mut jobs = load_jobs();
+    let content = add_job(&guild, &mut jobs, &name).await?;
+    save_jobs(jobs);
 
-    if let Some(_) = partial_guild.role_by_name(&name) {
-        msg.channel_id
-            .say(ctx, "Role with that name already exists.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+    guild.say(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Creates a role with a job which needs nobody in all existing stages.
+async fn add_job(guild: &impl GuildAccess, jobs: &mut Jobs, name: &str) -> Reply {
+    if role_by_name(&guild.roles().await?, name).is_some() {
+        return Err(BotError::rejected("Role with that name already exists."));
     }
 
-    let mut jobs = load_jobs();
     let points = match jobs.iter().next() {
         Some((_, job)) => job
             .points
             .iter()
             .map(|(player_count, _)| (*player_count, 0))
             .collect(),
-        None => IndexMap::new(),
+        None => Stages::default(),
+    };
+    let job = Job {
+        points,
+        instructions: None,
+        session_role: None,
+        voice_channel: None,
+        priority: 1,
+        tiers: Vec::new(),
     };
-    let job = Job { points };
 
-    let result = partial_guild
-        .create_role(&ctx.http, |r| r.name(name))
-        .await;
+    let role_id = guild.create_role(name).await?;
+    jobs.insert(role_id.0, job);
+    Ok("Role added succesfully.".to_owned())
+}
 
-    if let Err(_) = result {
-        msg.channel_id
-            .say(ctx, "Failed to create role.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+#[command]
+#[aliases("-")]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Remove a role by specifying it's name. Add `delete` after the name to also delete the Discord role.")]
+async fn remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let argc = args.len();
+    if argc != 1 && argc != 2 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
     }
 
-    let role = result.unwrap();
-    jobs.insert(role.id.0, job);
+    args.trimmed().quoted();
+    let name = args.single::<String>().unwrap();
+    let delete_role = match args.single::<String>().ok().as_deref() {
+        None => false,
+        Some("delete") => true,
+        Some(_) => {
+            return Err(BotError::rejected("Invalid option, expected `delete`.").into());
+        }
+    };
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let mut jobs = load_jobs();
+    let content = remove_job(&guild, &mut jobs, &name, delete_role).await?;
     save_jobs(jobs);
 
-    msg.channel_id
-        .say(ctx, "Role added succesfully.".to_owned())
-        .await
-        .ok();
+    guild.say(msg.channel_id, &content).await?;
     Ok(())
 }
 
+/// Removes a job, optionally deleting its role too.
+async fn remove_job(
+    guild: &impl GuildAccess,
+    jobs: &mut Jobs,
+    name: &str,
+    delete_role: bool,
+) -> Reply {
+    let role_id = match role_by_name(&guild.roles().await?, name) {
+        Some(role_id) => role_id,
+        None => return Err(BotError::rejected("Role doesn't exist.")),
+    };
+    if jobs.remove(&role_id.0).is_none() {
+        return Err(BotError::rejected("Role doesn't exist."));
+    }
+
+    if delete_role && guild.delete_role(role_id).await.is_err() {
+        return Ok("Role removed, but failed to delete the Discord role.".to_owned());
+    }
+    Ok("Role removed succesfully.".to_owned())
+}
+
 #[command]
-#[aliases("-")]
+#[aliases("l")]
 #[only_in(guilds)]
-#[description("Remove a role by specifying it's name.")]
-async fn remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    if args.len() != 1 {
-        msg.channel_id
-            .say(ctx, "Invalid amount of arguments.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+#[description("List all existing roles.")]
+async fn list(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    if !args.is_empty() {
+        return Err(BotError::rejected("Invalid amount of arguments").into());
+    }
+
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let content = list_jobs(&guild, &load_jobs()).await?;
+    guild.say(msg.channel_id, &content).await?;
+
+    Ok(())
+}
+
+async fn list_jobs(guild: &impl GuildAccess, jobs: &Jobs) -> Reply {
+    if jobs.is_empty() {
+        return Ok("No existing roles.".to_owned());
+    }
+
+    let roles = guild.roles().await?;
+    let mut response = String::new();
+    response.write_str("Existing roles:\n").ok();
+    for role_id in jobs.keys() {
+        let name = role_name(&roles, RoleId(*role_id));
+        response.write_fmt(format_args!("- {},\n", name)).ok();
+    }
+    Ok(response)
+}
+
+#[command]
+#[aliases("i")]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Set instructions sent to players assigned to a role. Specify the role name, followed by the instructions. Leave the instructions empty to remove them.")]
+async fn instructions(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.is_empty() {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
     }
 
     args.trimmed().quoted();
     let name = args.single::<String>().unwrap();
+    let text = args.rest().trim().to_owned();
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
     let mut jobs = load_jobs();
-    let partial_guild = msg
-        .guild_id
-        .unwrap()
-        .to_partial_guild(&ctx.http)
-        .await
-        .unwrap();
-    let result = partial_guild.role_by_name(&name);
-
-    if let None = result {
-        msg.channel_id
-            .say(ctx, "Role doesn't exist.".to_owned())
-            .await
-            .ok();
-        return Ok(());
-    }
-
-    let role = result.unwrap();
-    if let None = jobs.remove(&role.id.0) {
-        msg.channel_id
-            .say(ctx, "Role doesn't exist.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+    let content = set_instructions(&guild, &mut jobs, &name, text).await?;
+    save_jobs(jobs);
+
+    guild.say(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Sets or removes instructions of a job.
+async fn set_instructions(
+    guild: &impl GuildAccess,
+    jobs: &mut Jobs,
+    name: &str,
+    text: String,
+) -> Reply {
+    let job = match role_by_name(&guild.roles().await?, name) {
+        Some(role_id) => jobs.get_mut(&role_id.0),
+        None => None,
+    };
+    let job = match job {
+        Some(job) => job,
+        None => return Err(BotError::rejected("Role doesn't exist.")),
+    };
+
+    match text.is_empty() {
+        true => {
+            job.instructions = None;
+            Ok("Instructions removed succesfully.".to_owned())
+        }
+        false => {
+            job.instructions = Some(text);
+            Ok("Instructions set succesfully.".to_owned())
+        }
+    }
+}
+
+#[command("session")]
+#[aliases("s")]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Set the session role granted to players assigned to a role until the next roll or the end of the session. Specify the role name, followed by the session role name, which will be created if it doesn't exist. Leave the session role empty to stop granting one.")]
+async fn session_role(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let argc = args.len();
+    if argc != 1 && argc != 2 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
     }
 
+    args.trimmed().quoted();
+    let name = args.single::<String>().unwrap();
+    let session_name = args.single::<String>().ok();
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let mut jobs = load_jobs();
+    let content = set_session_role(&guild, &mut jobs, &name, session_name).await?;
     save_jobs(jobs);
 
-    msg.channel_id
-        .say(ctx, "Role removed succesfully.".to_owned())
-        .await
-        .ok();
+    guild.say(msg.channel_id, &content).await?;
     Ok(())
 }
 
+/// Sets or removes the session role of a job, creating the role if necessary.
+async fn set_session_role(
+    guild: &impl GuildAccess,
+    jobs: &mut Jobs,
+    name: &str,
+    session_name: Option<String>,
+) -> Reply {
+    let roles = guild.roles().await?;
+    let role_id = match role_by_name(&roles, name).filter(|role_id| jobs.contains_key(&role_id.0)) {
+        Some(role_id) => role_id,
+        None => return Err(BotError::rejected("Role doesn't exist.")),
+    };
+
+    let session_role = match session_name {
+        None => None,
+        Some(session_name) => match role_by_name(&roles, &session_name) {
+            Some(session_role_id) if jobs.contains_key(&session_role_id.0) => {
+                return Err(BotError::rejected("Session role can't be another role."));
+            }
+            Some(session_role_id) => Some(session_role_id.0),
+            None => Some(guild.create_role(&session_name).await?.0),
+        },
+    };
+    jobs.get_mut(&role_id.0).unwrap().session_role = session_role;
+
+    match session_role {
+        Some(_) => Ok("Session role set succesfully.".to_owned()),
+        None => Ok("Session role removed succesfully.".to_owned()),
+    }
+}
+
 #[command]
-#[aliases("l")]
+#[aliases("c")]
 #[only_in(guilds)]
-#[description("List all existing roles.")]
-async fn list(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    if args.len() != 0 {
-        msg.channel_id
-            .say(ctx, "Invalid amount of arguments".to_owned())
-            .await
-            .ok();
-        return Ok(());
+#[checks(Manager)]
+#[description("Set the voice channel players assigned to a role are moved to after a roll. Specify the role name, followed by the voice channel name. Leave the voice channel empty to stop moving players.")]
+async fn channel(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let argc = args.len();
+    if argc != 1 && argc != 2 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
     }
 
-    let jobs = load_jobs();
+    args.trimmed().quoted();
+    let name = args.single::<String>().unwrap();
+    let channel_name = args.single::<String>().ok();
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let mut jobs = load_jobs();
+    let content = set_voice_channel(&guild, &mut jobs, &name, channel_name).await?;
+    save_jobs(jobs);
+
+    guild.say(msg.channel_id, &content).await?;
+    Ok(())
+}
 
-    if jobs.len() == 0 {
-        msg.channel_id
-            .say(ctx, "No existing roles.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+/// Sets or removes the voice channel of a job.
+async fn set_voice_channel(
+    guild: &impl GuildAccess,
+    jobs: &mut Jobs,
+    name: &str,
+    channel_name: Option<String>,
+) -> Reply {
+    let role_id = role_by_name(&guild.roles().await?, name)
+        .filter(|role_id| jobs.contains_key(&role_id.0));
+    let role_id = match role_id {
+        Some(role_id) => role_id,
+        None => return Err(BotError::rejected("Role doesn't exist.")),
+    };
+
+    let voice_channel = match channel_name {
+        None => None,
+        Some(channel_name) => {
+            let channels = guild.voice_channels().await?;
+            let channel_id = channels
+                .iter()
+                .find(|(_channel_id, name)| **name == channel_name)
+                .map(|(channel_id, _name)| *channel_id);
+            match channel_id {
+                Some(channel_id) => Some(channel_id.0),
+                None => return Err(BotError::rejected("Voice channel doesn't exist.")),
+            }
+        }
+    };
+    jobs.get_mut(&role_id.0).unwrap().voice_channel = voice_channel;
+
+    match voice_channel {
+        Some(_) => Ok("Voice channel set succesfully.".to_owned()),
+        None => Ok("Voice channel removed succesfully.".to_owned()),
     }
+}
 
-    let mut response = String::new();
-    let partial_guild = msg
-        .guild_id
-        .unwrap()
-        .to_partial_guild(&ctx.http)
-        .await
-        .unwrap();
+#[command]
+#[aliases("p")]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Set the priority of a role when the `weighted-priority` apportionment gives out remaining players. Specify the role name, followed by a positive weight, 1 by default.")]
+async fn priority(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() != 2 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
 
-    response.write_str("Existing roles:\n").ok();
-    for (role_id, _job) in jobs {
-        let role = partial_guild.roles.get(&RoleId(role_id)).unwrap();
-        response.write_fmt(format_args!("- {},\n", role.name)).ok();
+    args.trimmed().quoted();
+    let name = args.single::<String>().unwrap();
+    let weight = match args.single::<u16>() {
+        Ok(weight) if weight > 0 => weight,
+        _ => return Err(BotError::rejected("Invalid priority, expected a positive number.").into()),
+    };
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let mut jobs = load_jobs();
+    let content = set_priority(&guild, &mut jobs, &name, weight).await?;
+    save_jobs(jobs);
+
+    guild.say(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+#[command]
+#[aliases("t")]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Set other roles which qualify players for a role, like `\"Runner (backup)\"`. Specify the role name, followed by the qualifying roles from the highest tier to the lowest. Players with the role itself are always preferred, then the highest tier. Leave the qualifying roles empty to only accept the role itself.")]
+async fn tiers(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.is_empty() {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
     }
-    msg.channel_id.say(ctx, response).await.ok();
 
+    args.trimmed().quoted();
+    let name = args.single::<String>().unwrap();
+    let mut tier_names = Vec::new();
+    while let Ok(tier_name) = args.single::<String>() {
+        tier_names.push(tier_name);
+    }
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let mut jobs = load_jobs();
+    let content = set_tiers(&guild, &mut jobs, &name, &tier_names).await?;
+    save_jobs(jobs);
+
+    guild.say(msg.channel_id, &content).await?;
     Ok(())
 }
 
+/// Sets the roles qualifying for a job besides its own, from the highest tier.
+async fn set_tiers(
+    guild: &impl GuildAccess,
+    jobs: &mut Jobs,
+    name: &str,
+    tier_names: &[String],
+) -> Reply {
+    let roles = guild.roles().await?;
+    let role_id = match role_by_name(&roles, name).filter(|role_id| jobs.contains_key(&role_id.0)) {
+        Some(role_id) => role_id,
+        None => return Err(BotError::rejected("Role doesn't exist.")),
+    };
+
+    let mut tiers = Vec::new();
+    for tier_name in tier_names {
+        match role_by_name(&roles, tier_name) {
+            Some(tier_role_id) if tier_role_id == role_id || tiers.contains(&tier_role_id.0) => {
+                return Err(BotError::Rejected(format!("'{}' is listed more than once.", tier_name)));
+            }
+            Some(tier_role_id) => tiers.push(tier_role_id.0),
+            None => return Err(BotError::Rejected(format!("Role '{}' doesn't exist.", tier_name))),
+        }
+    }
+    let content = match tiers.is_empty() {
+        true => "Tiers removed succesfully.".to_owned(),
+        false => format!("Tiers set succesfully, {} roles qualify besides '{}'.", tiers.len(), name),
+    };
+    jobs.get_mut(&role_id.0).unwrap().tiers = tiers;
+    Ok(content)
+}
+
+/// Sets the priority of a job.
+async fn set_priority(guild: &impl GuildAccess, jobs: &mut Jobs, name: &str, weight: u16) -> Reply {
+    let job = match role_by_name(&guild.roles().await?, name) {
+        Some(role_id) => jobs.get_mut(&role_id.0),
+        None => None,
+    };
+    match job {
+        Some(job) => {
+            job.priority = weight;
+            Ok(format!("Priority of '{}' set to {}.", name, weight))
+        }
+        None => Err(BotError::rejected("Role doesn't exist.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::runner_guild;
+
+    #[tokio::test]
+    async fn add_creates_role_with_existing_stages() {
+        let (guild, mut jobs, _runner) = runner_guild(&[(4, 1)]);
+
+        let reply = add_job(&guild, &mut jobs, "Reader").await;
+
+        assert!(reply.is_ok());
+        let reader = guild.role_id("Reader").unwrap();
+        assert_eq!(jobs[&reader.0].points.get(&4), Some(&0));
+    }
+
+    #[tokio::test]
+    async fn add_rejects_existing_role() {
+        let (guild, mut jobs, _runner) = runner_guild(&[(4, 1)]);
+
+        let reply = add_job(&guild, &mut jobs, "Runner").await;
+
+        assert_eq!(reply.unwrap_err().to_string(), "Role with that name already exists.");
+        assert_eq!(jobs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn remove_keeps_role_unless_asked() {
+        let (guild, mut jobs, runner) = runner_guild(&[(4, 1)]);
+
+        assert!(remove_job(&guild, &mut jobs, "Runner", false).await.is_ok());
+
+        assert!(jobs.is_empty());
+        assert_eq!(guild.role_id("Runner"), Some(runner));
+    }
+
+    #[tokio::test]
+    async fn remove_deletes_role() {
+        let (guild, mut jobs, _runner) = runner_guild(&[(4, 1)]);
+
+        assert!(remove_job(&guild, &mut jobs, "Runner", true).await.is_ok());
+
+        assert!(jobs.is_empty());
+        assert_eq!(guild.role_id("Runner"), None);
+    }
+
+    #[tokio::test]
+    async fn remove_rejects_unknown_role() {
+        let (guild, mut jobs, _runner) = runner_guild(&[(4, 1)]);
+        guild.add_role("Spectator");
+
+        let reply = remove_job(&guild, &mut jobs, "Spectator", false).await;
+
+        assert_eq!(reply.unwrap_err().to_string(), "Role doesn't exist.");
+        assert_eq!(jobs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn list_names_roles() {
+        let (guild, jobs, _runner) = runner_guild(&[(4, 1)]);
+
+        assert_eq!(list_jobs(&guild, &jobs).await.unwrap(), "Existing roles:\n- Runner,\n");
+        assert_eq!(list_jobs(&guild, &Jobs::new()).await.unwrap(), "No existing roles.");
+    }
+
+    #[tokio::test]
+    async fn instructions_are_set_and_removed() {
+        let (guild, mut jobs, runner) = runner_guild(&[(4, 1)]);
+
+        assert!(set_instructions(&guild, &mut jobs, "Runner", "Go fast.".to_owned()).await.is_ok());
+        assert_eq!(jobs[&runner.0].instructions.as_deref(), Some("Go fast."));
+
+        assert!(set_instructions(&guild, &mut jobs, "Runner", String::new()).await.is_ok());
+        assert_eq!(jobs[&runner.0].instructions, None);
+    }
+
+    #[tokio::test]
+    async fn session_role_is_created() {
+        let (guild, mut jobs, runner) = runner_guild(&[(4, 1)]);
+
+        let reply = set_session_role(&guild, &mut jobs, "Runner", Some("Running".to_owned())).await;
+
+        assert!(reply.is_ok());
+        let running = guild.role_id("Running").unwrap();
+        assert_eq!(jobs[&runner.0].session_role, Some(running.0));
+    }
+
+    #[tokio::test]
+    async fn session_role_cant_be_a_job() {
+        let (guild, mut jobs, runner) = runner_guild(&[(4, 1)]);
+
+        let reply = set_session_role(&guild, &mut jobs, "Runner", Some("Runner".to_owned())).await;
+
+        assert_eq!(reply.unwrap_err().to_string(), "Session role can't be another role.");
+        assert_eq!(jobs[&runner.0].session_role, None);
+    }
+
+    #[tokio::test]
+    async fn voice_channel_must_exist() {
+        let (guild, mut jobs, runner) = runner_guild(&[(4, 1)]);
+        let track = guild.add_voice_channel("Track");
+
+        let reply = set_voice_channel(&guild, &mut jobs, "Runner", Some("Pit".to_owned())).await;
+        assert_eq!(reply.unwrap_err().to_string(), "Voice channel doesn't exist.");
+
+        let reply = set_voice_channel(&guild, &mut jobs, "Runner", Some("Track".to_owned())).await;
+        assert!(reply.is_ok());
+        assert_eq!(jobs[&runner.0].voice_channel, Some(track.0));
+    }
+
+    #[tokio::test]
+    async fn sets_priority() {
+        let (guild, mut jobs, runner) = runner_guild(&[(4, 1)]);
+
+        let reply = set_priority(&guild, &mut jobs, "Pit crew", 3).await;
+        assert_eq!(reply.unwrap_err().to_string(), "Role doesn't exist.");
+
+        let reply = set_priority(&guild, &mut jobs, "Runner", 3).await;
+        assert_eq!(reply.unwrap(), "Priority of 'Runner' set to 3.");
+        assert_eq!(jobs[&runner.0].priority, 3);
+    }
+
+    #[tokio::test]
+    async fn tiers_must_exist() {
+        let (guild, mut jobs, runner) = runner_guild(&[(4, 1)]);
+        let backup = guild.add_role("Runner (backup)");
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
+        let reply = set_tiers(&guild, &mut jobs, "Runner", &names(&["Runner (spare)"])).await;
+        assert_eq!(reply.unwrap_err().to_string(), "Role 'Runner (spare)' doesn't exist.");
+        let reply = set_tiers(&guild, &mut jobs, "Runner", &names(&["Runner"])).await;
+        assert_eq!(reply.unwrap_err().to_string(), "'Runner' is listed more than once.");
+        assert!(jobs[&runner.0].tiers.is_empty());
+
+        let reply = set_tiers(&guild, &mut jobs, "Runner", &names(&["Runner (backup)"])).await;
+        assert!(reply.is_ok());
+        assert_eq!(jobs[&runner.0].tiers, vec![backup.0]);
+    }
+}
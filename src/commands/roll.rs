@@ -1,6 +1,11 @@
-use crate::model::{load_jobs, Job};
-use indexmap::{IndexMap, IndexSet};
-use rand::{distributions::Uniform, prelude::Distribution};
+use crate::{
+    error::BotError,
+    model::{
+        load_guild_config, load_guild_qualifications, load_jobs, load_session, lock_session, save_session, GuildQualifications, Job, Jobs, Session,
+    },
+};
+use indexmap::IndexMap;
+use role_dispatch_core::{Apportionment, PairingError};
 use serenity::{
     framework::standard::{macros::command, Args, CommandResult},
     model::prelude::*,
@@ -8,206 +13,458 @@ use serenity::{
 };
 use std::{
     collections::{HashMap, HashSet},
-    fmt::Write, cmp::Ordering,
+    fmt::Write,
 };
 
-use super::util::{
-    get_callers_vc, get_members_in_vc, get_users_roles, remove_excluded,
-    remove_irrelevant_qualifications,
+use super::{
+    guild::{DiscordGuild, GuildAccess},
+    schedule::{cancel_rolls, schedule_rolls, MIN_INTERVAL},
+    session::{grant_session_roles, record_round, split_voice_channels},
+    stats::record_roll,
+    util::{
+        format_duration, get_callers_vc, get_members, get_members_in_vc, parse_duration,
+        merge_qualifications, qualification_tiers, remove_excluded, remove_irrelevant_qualifications, role_name, Reply,
+    },
 };
 
+/// Amount of simulated rolls used to estimate odds in a preview.
+const PREVIEW_TRIALS: u32 = 1000;
+
 #[command]
 #[aliases("r")]
 #[only_in(guilds)]
-#[description("Assigns roles to all players in the caller's voice channel.")]
-pub async fn roll(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
-    try_assigning(&ctx, &msg).await;
+#[description("Assigns roles to all players in the caller's voice channel. Add `--dry-run` to only preview the roll, `every` followed by a duration like `15m` to keep rolling on a timer or `stop` to stop the timer.")]
+pub async fn roll(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    args.trimmed().quoted();
+    match args.single::<String>().ok().as_deref() {
+        None => {
+            try_assigning(ctx, msg).await?;
+        }
+        Some("--dry-run") => {
+            try_previewing(ctx, msg).await?;
+        }
+        Some("stop") => {
+            let content = match cancel_rolls(ctx, msg.channel_id).await {
+                true => "Stopped rolling on a timer.",
+                false => "No rolls are scheduled in this channel.",
+            };
+            msg.channel_id.say(ctx, content.to_owned()).await?;
+        }
+        Some(option) if option.starts_with("every") => {
+            let interval = match option.trim_start_matches("every").trim() {
+                "" => args.single::<String>().ok(),
+                interval => Some(interval.to_owned()),
+            }
+            .and_then(|interval| parse_duration(&interval))
+            .filter(|interval| *interval >= MIN_INTERVAL);
+            match interval {
+                Some(interval) => {
+                    let target = RollTarget::from_message(ctx, msg).await?;
+                    if target.voice_channel_id.is_none() {
+                        return Err(BotError::NotInVoiceChannel.into());
+                    }
+                    schedule_rolls(ctx, target, interval).await;
+                    msg.channel_id
+                        .say(
+                            ctx,
+                            format!(
+                                "Rolling every {} until `roll stop`.",
+                                format_duration(interval)
+                            ),
+                        )
+                        .await?;
+                }
+                None => {
+                    return Err(BotError::rejected(
+                        "Invalid interval, expected a duration of at least `1m`.",
+                    )
+                    .into());
+                }
+            }
+        }
+        Some(_) => {
+            return Err(
+                BotError::rejected("Invalid option, expected `--dry-run`, `every` or `stop`.").into(),
+            );
+        }
+    }
 
     Ok(())
 }
 
+#[command]
+#[only_in(guilds)]
+#[description("Previews a roll for the caller's voice channel without assigning anyone. Shows how many players each role needs, how many are qualified and every player's odds for each role.")]
+pub async fn preview(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    try_previewing(ctx, msg).await?;
+
+    Ok(())
+}
+
+/// Where a roll is made and announced.
+#[derive(Debug, Clone, Copy)]
+pub struct RollTarget {
+    pub guild_id: GuildId,
+    /// Text channel the result is posted in.
+    pub channel_id: ChannelId,
+    /// Voice channel of the participating players.
+    pub voice_channel_id: Option<ChannelId>,
+}
+
+impl RollTarget {
+    /// Targets the channel of a message and the voice channel of its author.
+    pub async fn from_message(ctx: &Context, msg: &Message) -> Result<Self, BotError> {
+        let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+        Ok(Self {
+            guild_id,
+            channel_id: msg.channel_id,
+            voice_channel_id: get_callers_vc(&DiscordGuild::new(ctx, guild_id), msg.author.id).await,
+        })
+    }
+}
+
 /// Decide role for every participating player
-async fn try_assigning(ctx: &Context, msg: &Message) -> Option<Message> {
-    if let Some(voice_channel_id) = get_callers_vc(ctx, msg).await {
-        let members = get_members_in_vc(ctx, msg, voice_channel_id).await;
-        let mut users_roles = get_users_roles(members);
-        remove_excluded(&mut users_roles);
-        let mut jobs = load_jobs();
-        remove_irrelevant_qualifications(&mut users_roles, &jobs);
-        let assigned = decide_pairings(&mut jobs, &users_roles);
-        return Some(display_pairings(ctx, msg, assigned).await);
+async fn try_assigning(ctx: &Context, msg: &Message) -> Result<(), BotError> {
+    let target = RollTarget::from_message(ctx, msg).await?;
+    assign_roles(ctx, target).await?;
+    Ok(())
+}
+
+/// Decide role for every participating player and announce it.
+/// Returns nothing if there was no one to roll for.
+pub async fn assign_roles(
+    ctx: &Context,
+    target: RollTarget,
+) -> Result<Option<Pairings>, BotError> {
+    let guild_id = target.guild_id;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let lock = lock_session(guild_id.0).await;
+    let mut session = load_session(guild_id.0);
+    // Players split into role channels are still rolled together with the original channel
+    let voice_channel_id = match session.origin_channel {
+        Some(origin_channel) => Some(ChannelId(origin_channel)),
+        None => target.voice_channel_id,
+    };
+    let mut jobs = load_jobs();
+    let qualifications = load_guild_qualifications(guild_id.0);
+    let guild_config = load_guild_config(guild_id.0);
+    let excluded = guild_config.excluded_role;
+    let users_roles = gather_players(&guild, &session, voice_channel_id, &jobs, excluded, &qualifications);
+    let users_roles = match users_roles.await {
+        Some(users_roles) => users_roles,
+        None => return Ok(None),
+    };
+    let assigned = decide_pairings(&mut jobs, &users_roles, guild_config.apportionment);
+    let round = match &assigned {
+        Ok(_) if session.is_active() => Some(session.rounds.len() + 1),
+        _ => None,
+    };
+    let roles = guild.roles().await?;
+    let content = format_pairings(&roles, round, assigned.clone());
+    guild.say(target.channel_id, &content).await?;
+    if let Ok(assigned) = &assigned {
+        // Only announced rolls count as rounds and towards the history
+        if session.is_active() {
+            record_round(&mut session, assigned);
+        }
+        record_roll(guild_id, assigned);
+        #[cfg(feature = "http-api")]
+        if !assigned.is_empty() {
+            crate::http::overlay::publish_roll(ctx, guild_id, round, assigned, &roles).await;
+        }
+        let granted = grant_session_roles(&guild, &mut session, &jobs, assigned).await;
+        if let Some(voice_channel_id) = voice_channel_id {
+            split_voice_channels(&guild, &mut session, voice_channel_id, &jobs, assigned).await;
+        }
+        save_session(guild_id.0, session);
+        drop(lock);
+        if guild_config.direct_messages {
+            send_instructions(&guild, &jobs, assigned).await;
+        }
+        // The roll still stands, but the caller should know their session role is gone.
+        granted?;
+    }
+    Ok(Some(assigned))
+}
+
+/// Returns players taking part in a roll with their relevant qualifications,
+/// nothing if there is no one to roll for.
+async fn gather_players(
+    guild: &impl GuildAccess,
+    session: &Session,
+    voice_channel_id: Option<ChannelId>,
+    jobs: &Jobs,
+    excluded: Option<u64>,
+    qualifications: &GuildQualifications,
+) -> Option<HashMap<UserId, HashSet<RoleId>>> {
+    let mut users_roles = match (session.is_active(), voice_channel_id) {
+        (true, _) => get_members(guild, &session.participants).await,
+        (false, Some(voice_channel_id)) => {
+            gather_voice_members(guild, voice_channel_id, jobs, &session.moved_players).await
+        }
+        (false, None) => return None,
+    };
+    merge_qualifications(&mut users_roles, qualifications);
+    remove_excluded(&mut users_roles, excluded);
+    remove_irrelevant_qualifications(&mut users_roles, jobs);
+    Some(users_roles)
+}
+
+/// Returns members of a voice channel, together with players moved from it into role channels.
+async fn gather_voice_members(
+    guild: &impl GuildAccess,
+    voice_channel_id: ChannelId,
+    jobs: &Jobs,
+    moved_players: &[u64],
+) -> HashMap<UserId, HashSet<RoleId>> {
+    let mut members = get_members_in_vc(guild, voice_channel_id).await;
+    if moved_players.is_empty() {
+        return members;
+    }
+    let mut split_channels: Vec<ChannelId> = jobs
+        .values()
+        .filter_map(|job| job.voice_channel.map(ChannelId))
+        .filter(|channel_id| *channel_id != voice_channel_id)
+        .collect();
+    split_channels.sort();
+    split_channels.dedup();
+    for channel_id in split_channels {
+        let split_members = get_members_in_vc(guild, channel_id).await;
+        members.extend(
+            split_members
+                .into_iter()
+                .filter(|(user_id, _roles)| moved_players.contains(&user_id.0)),
+        );
+    }
+    members
+}
+
+/// Estimate the outcome of a roll without assigning anyone
+async fn try_previewing(ctx: &Context, msg: &Message) -> Result<(), BotError> {
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let voice_channel_id = get_callers_vc(&guild, msg.author.id)
+        .await
+        .ok_or(BotError::NotInVoiceChannel)?;
+    let guild_config = load_guild_config(guild.id().0);
+    let qualifications = load_guild_qualifications(guild.id().0);
+    let content = preview_roll(
+        &guild,
+        load_jobs(),
+        voice_channel_id,
+        guild_config.excluded_role,
+        &qualifications,
+        guild_config.apportionment,
+    )
+    .await?;
+    guild.say_quietly(msg.channel_id, &content).await
+}
+
+/// Describes the likely outcome of a roll for a voice channel.
+async fn preview_roll(
+    guild: &impl GuildAccess,
+    mut jobs: Jobs,
+    voice_channel_id: ChannelId,
+    excluded: Option<u64>,
+    qualifications: &GuildQualifications,
+    method: Apportionment,
+) -> Reply {
+    if jobs.is_empty() {
+        return Ok("No roles to preview.".to_owned());
+    }
+
+    let mut users_roles = get_members_in_vc(guild, voice_channel_id).await;
+    merge_qualifications(&mut users_roles, qualifications);
+    let mut names: HashMap<UserId, String> = HashMap::new();
+    for user_id in users_roles.keys() {
+        names.insert(*user_id, guild.display_name(*user_id).await);
+    }
+    let present: Vec<UserId> = users_roles.keys().copied().collect();
+    remove_excluded(&mut users_roles, excluded);
+    let included: Vec<UserId> = users_roles.keys().copied().collect();
+    remove_irrelevant_qualifications(&mut users_roles, &jobs);
+    let mut excluded: Vec<&str> = present
+        .iter()
+        .filter(|user_id| !included.contains(user_id))
+        .map(|user_id| names[user_id].as_str())
+        .collect();
+    excluded.sort_unstable();
+    let mut unqualified: Vec<&str> = included
+        .iter()
+        .filter(|user_id| !users_roles.contains_key(user_id))
+        .map(|user_id| names[user_id].as_str())
+        .collect();
+    unqualified.sort_unstable();
+
+    let quotas = decide_quotas(&mut jobs, users_roles.len() as u16, method);
+    let odds = estimate_odds(&mut jobs, &users_roles, method, PREVIEW_TRIALS);
+    let roles = guild.roles().await?;
+
+    let mut content = String::new();
+    content
+        .write_fmt(format_args!("Preview for {} players:\n", users_roles.len()))
+        .ok();
+    let mut quotas: Vec<(RoleId, u16)> = quotas.into_iter().collect();
+    quotas.sort();
+    for (role_id, needed) in quotas.iter() {
+        let candidates = users_roles
+            .values()
+            .filter(|roles| qualification_tiers(&jobs, roles).contains_key(role_id))
+            .count();
+        content
+            .write_fmt(format_args!(
+                "**{}**: {} needed, {} qualified{}\n",
+                role_name(&roles, *role_id),
+                needed,
+                candidates,
+                if candidates < *needed as usize { " (not enough)" } else { "" }
+            ))
+            .ok();
+    }
+    if !excluded.is_empty() {
+        content
+            .write_fmt(format_args!("Excluded: {}\n", excluded.join(", ")))
+            .ok();
+    }
+    if !unqualified.is_empty() {
+        content
+            .write_fmt(format_args!("Not qualified for any role: {}\n", unqualified.join(", ")))
+            .ok();
+    }
+    if !users_roles.is_empty() {
+        content.write_str("\nOdds:\n").ok();
+        let mut players: Vec<(&UserId, &HashMap<RoleId, u32>)> = odds.assigned.iter().collect();
+        players.sort_by_key(|(user_id, _counts)| names[user_id].to_lowercase());
+        for (user_id, counts) in players {
+            let mut counts: Vec<(&RoleId, &u32)> = counts.iter().collect();
+            counts.sort_by(|(_role_id_a, a), (_role_id_b, b)| b.cmp(a));
+            let chances: Vec<String> = counts
+                .into_iter()
+                .map(|(role_id, count)| {
+                    format!(
+                        "{} {:.0}%",
+                        role_name(&roles, *role_id),
+                        *count as f64 * 100. / PREVIEW_TRIALS as f64
+                    )
+                })
+                .collect();
+            content
+                .write_fmt(format_args!("- {}: {}\n", names[user_id], chances.join(", ")))
+                .ok();
+        }
+    }
+    if let Some((role_id, _failures)) = odds.failed.iter().max_by_key(|(_role_id, failures)| **failures) {
+        let failures: u32 = odds.failed.values().sum();
+        content
+            .write_fmt(format_args!(
+                "Rolls fail {:.0}% of the time, mostly on '{}'.\n",
+                failures as f64 * 100. / PREVIEW_TRIALS as f64,
+                role_name(&roles, *role_id)
+            ))
+            .ok();
+    }
+    Ok(content)
+}
+
+/// How often each player got each job and which jobs failed over many rolls.
+struct Odds {
+    assigned: HashMap<UserId, HashMap<RoleId, u32>>,
+    failed: HashMap<RoleId, u32>,
+}
+
+/// Repeats the assignment to estimate every player's odds for each job.
+fn estimate_odds(
+    jobs: &mut HashMap<u64, Job>,
+    users_roles: &HashMap<UserId, HashSet<RoleId>>,
+    method: Apportionment,
+    trials: u32,
+) -> Odds {
+    let mut odds = Odds {
+        assigned: users_roles.keys().map(|user_id| (*user_id, HashMap::new())).collect(),
+        failed: HashMap::new(),
+    };
+    for _ in 0..trials {
+        match decide_pairings(jobs, users_roles, method) {
+            Ok(assigned) => {
+                for (role_id, players) in assigned {
+                    for user_id in players {
+                        let counts = odds.assigned.get_mut(&user_id).unwrap();
+                        *counts.entry(role_id).or_insert(0) += 1;
+                    }
+                }
+            }
+            Err(PairingError::Unqualified(role_id)) => {
+                *odds.failed.entry(role_id).or_insert(0) += 1;
+            }
+            // Can't happen with any job, previews need some
+            Err(PairingError::NoSlotsLeft) => {}
+        }
+    }
+    odds
+}
+
+/// Assigned players of every job, or why they couldn't be assigned.
+pub type Pairings = Result<IndexMap<RoleId, Vec<UserId>>, PairingError<RoleId>>;
+
+/// Describes why players couldn't be assigned, naming roles with `name`.
+pub fn describe_failure(error: PairingError<RoleId>, name: impl Fn(RoleId) -> String) -> String {
+    match error {
+        PairingError::Unqualified(role_id) => {
+            format!("Not enough players qualified for role {}.", name(role_id))
+        }
+        PairingError::NoSlotsLeft => "Not enough roles for every player.".to_owned(),
     }
-    None
 }
 
-#[derive(Debug)]
-struct JobExt {
-    at_least: u32,
-    assigned: u32,
-    players: IndexSet<UserId>,
+/// Decide how many players each job needs.
+pub fn decide_quotas(
+    jobs: &mut HashMap<u64, Job>,
+    amount: u16,
+    method: Apportionment,
+) -> IndexMap<RoleId, u16> {
+    role_dispatch_core::decide_quotas(
+        jobs.iter_mut()
+            .map(|(role_id, job)| (RoleId(*role_id), &mut job.points, job.priority)),
+        amount,
+        method,
+        &mut rand::thread_rng(),
+    )
 }
 
 /// Decide pairings.
-/// Decide how many of each role based on the proportions and limits
-/// Finish by applying last step of Hungarian Algorithm.
+/// Decide how many of each role based on the proportions and limits,
+/// then assign qualified players to them.
 /// Returns error if there was a problem during assignment.
 pub fn decide_pairings(
     jobs: &mut HashMap<u64, Job>,
     users_roles: &HashMap<UserId, HashSet<RoleId>>,
-) -> Result<IndexMap<RoleId, Vec<UserId>>, RoleId> {
-    /// Variables required for assigning jobs.
-    #[derive(Debug)]
-    struct AssigningJob {
-        needed: u16,
-        leftovers: f64,
-        players: IndexSet<UserId>,
-    }
-
-    /// Variables required for assigning players.
-    #[derive(Debug)]
-    struct AssigningPlayer {}
-
-    /// Assigns a player to a job.
-    /// Update all the variables.
-    /// Remove used players and jobs.
-    fn assign(
-        assigned: &mut IndexMap<RoleId, Vec<UserId>>,
-        left_jobs: &mut IndexMap<RoleId, AssigningJob>,
-        left_players: &mut IndexMap<UserId, AssigningPlayer>,
-        role_id: RoleId,
-        user_id: UserId,
-    ) {
-        // Update `assigned`
-        if let Some(vec) = match assigned.get_mut(&role_id) {
-            Some(vec) => {
-                vec.push(user_id);
-                None
-            }
-            None => {
-                let mut vec = Vec::new();
-                vec.push(user_id);
-                Some(vec)
-            }
-        } {
-            assigned.insert(role_id, vec);
-        };
-        // Update `left_jobs`
-        let job = left_jobs.get_mut(&role_id).unwrap();
-        job.needed -= 1;
-        if job.needed <= 0 {
-            left_jobs.remove(&role_id);
-        }
-        left_jobs.iter_mut().for_each(|(_role_id, job)| {
-            job.players.remove(&user_id);
-        });
-        // Update `left_players`
-        left_players.remove(&user_id);
-    }
-
-    // Initialize assignment variables
-    let amount = users_roles.len() as u16;
-    let mut used_amount: u16 = 0;
-    let mut assigned: IndexMap<RoleId, Vec<UserId>> = IndexMap::new();
-    let mut left_jobs: IndexMap<RoleId, AssigningJob> = jobs
-        .iter_mut()
-        .map(|(role_id, job)| {
-            let interpolated = job.interpolate(amount);
-            let full = interpolated.trunc() as u16;
-            used_amount += full;
-            (
-                RoleId(*role_id),
-                AssigningJob {
-                    needed: full,
-                    leftovers: interpolated.fract(),
-                    players: users_roles
-                        .iter()
-                        .filter_map(|(user_id, roles)| {
-                            if roles.get(&RoleId(*role_id)).is_some() {
-                                Some(*user_id)
-                            } else {
-                                None
-                            }
-                        })
-                        .collect(),
-                },
-            )
-        })
-        .collect();
-    let mut for_assignment: IndexMap<RoleId, AssigningJob> = IndexMap::new();
-    let rng = &mut rand::thread_rng();
-
-    // Distribute based on the remaining fractions
-    // Check if too many players
-    for _ in 0..(amount - used_amount) {
-        let (role_id, _job) = left_jobs
-            .iter_mut()
-            .max_by(|(_role_id_a, job_a), (_role_id_b, job_b)| {
-                job_a
-                    .leftovers
-                    .partial_cmp(&job_b.leftovers)
-                    .unwrap_or(Ordering::Equal)
-            })
-            .unwrap();
-        let role_id = *role_id;
-        let mut job = left_jobs.remove(&role_id).unwrap();
-        job.leftovers = 0.;
-        job.needed += 1;
-        for_assignment.insert(role_id, job);
-    }
-    left_jobs.into_iter().for_each(|(role_id, mut job)| {
-        job.leftovers = 0.;
-        for_assignment.insert(role_id, job);
-    });
-    let mut left_jobs = for_assignment;
-    // Initialize more assignment variables
-    let mut left_players: IndexMap<UserId, AssigningPlayer> = users_roles
+    method: Apportionment,
+) -> Pairings {
+    let quotas = decide_quotas(jobs, users_roles.len() as u16, method);
+    let players: HashMap<UserId, HashMap<RoleId, u8>> = users_roles
         .iter()
-        .map(|(user_id, _roles)| (*user_id, AssigningPlayer {}))
+        .map(|(user_id, roles)| (*user_id, qualification_tiers(jobs, roles)))
         .collect();
-    // Assign players to roles
-    for _ in 0..left_players.len() {
-        let (role_id, job) = left_jobs
-            .iter()
-            .filter(|(_role_id, job)| job.needed > 0)
-            .min_by_key(|(_role_id, job)| job.players.len())
-            .unwrap();
-        let role_id = *role_id;
-        let available = job.players.len();
-        // Check if any players are available
-        if available == 0 {
-            return Err(role_id);
-        }
-        let user_id = *job
-            .players
-            .get_index(Uniform::from(0..available).sample(rng))
-            .unwrap();
-        assign(
-            &mut assigned,
-            &mut left_jobs,
-            &mut left_players,
-            role_id,
-            user_id,
-        );
-    }
-    Ok(assigned)
+    role_dispatch_core::decide_pairings(quotas, &players, &mut rand::thread_rng())
 }
 
-/// Displays the distribution of roles or the error.
-async fn display_pairings(
-    ctx: &Context,
-    msg: &Message,
-    assigned: Result<IndexMap<RoleId, Vec<UserId>>, RoleId>,
-) -> Message {
-    let roles = msg.guild_id.unwrap().roles(&ctx.http).await.unwrap();
-    let content = match assigned {
+/// Describes the distribution of roles or the error.
+fn format_pairings(
+    roles: &HashMap<RoleId, String>,
+    round: Option<usize>,
+    assigned: Pairings,
+) -> String {
+    match assigned {
         Ok(mut assigned) => match assigned.len() {
             0 => "No players.".to_owned(),
             _ => {
                 let mut content = String::new();
+                if let Some(round) = round {
+                    content.write_fmt(format_args!("**Round {}**\n", round)).ok();
+                }
                 assigned.sort_keys();
                 assigned.into_iter().for_each(|(role_id, players)| {
                     content
-                        .write_fmt(format_args!("{}:\n", roles.get(&role_id).unwrap().name))
+                        .write_fmt(format_args!("{}:\n", role_name(roles, role_id)))
                         .ok();
                     players.into_iter().for_each(|user_id| {
                         content.write_fmt(format_args!("- <@{}>,\n", user_id)).ok();
@@ -217,11 +474,198 @@ async fn display_pairings(
                 content
             }
         },
-        Err(role_id) => format!(
-            "Not enough players qualified for role '{}'.",
-            roles.get(&role_id).unwrap().name
-        ),
-    };
+        Err(error) => describe_failure(error, |role_id| format!("'{}'", role_name(roles, role_id))),
+    }
+}
+
+/// Sends every assigned player their job and its instructions.
+async fn send_instructions(
+    guild: &impl GuildAccess,
+    jobs: &HashMap<u64, Job>,
+    assigned: &IndexMap<RoleId, Vec<UserId>>,
+) {
+    let guild_name = guild.name().await.unwrap_or_else(|| "the server".to_owned());
+    let roles = guild.roles().await.unwrap_or_default();
+    for (role_id, players) in assigned {
+        let mut content = format!(
+            "You were assigned **{}** in {}.",
+            role_name(&roles, *role_id),
+            guild_name
+        );
+        if let Some(instructions) = jobs.get(&role_id.0).and_then(|job| job.instructions.as_ref()) {
+            content.write_fmt(format_args!("\n\n{}", instructions)).ok();
+        }
+        for user_id in players {
+            guild.direct_message(*user_id, &content).await.ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::{job, runner_guild, FakeGuild};
+
+    /// Guild with a "Runner" job needing everyone and a voice channel.
+    fn lobby_guild() -> (FakeGuild, Jobs, RoleId, ChannelId) {
+        let (guild, jobs, runner) = runner_guild(&[(1, 1)]);
+        let voice_channel_id = guild.add_voice_channel("Lobby");
+        (guild, jobs, runner, voice_channel_id)
+    }
+
+    #[tokio::test]
+    async fn gathers_qualified_players_in_voice_channel() {
+        let (guild, jobs, runner, lobby) = lobby_guild();
+        let excluded = guild.add_role("Excluded");
+        let qualified = guild.add_member("Qualified", &[runner]);
+        let unqualified = guild.add_member("Unqualified", &[]);
+        let resting = guild.add_member("Resting", &[runner, excluded]);
+        let elsewhere = guild.add_member("Elsewhere", &[runner]);
+        for user_id in [qualified, unqualified, resting] {
+            guild.join_voice(user_id, lobby);
+        }
+        let other = guild.add_voice_channel("Other");
+        guild.join_voice(elsewhere, other);
+
+        let players = gather_players(&guild, &Session::default(), Some(lobby), &jobs, Some(excluded.0), &GuildQualifications::new())
+            .await
+            .unwrap();
+
+        assert_eq!(players.keys().collect::<Vec<_>>(), vec![&qualified]);
+    }
+
+    #[tokio::test]
+    async fn nobody_to_roll_without_voice_channel() {
+        let (guild, jobs, _runner, _lobby) = lobby_guild();
+
+        assert!(gather_players(&guild, &Session::default(), None, &jobs, None, &GuildQualifications::new()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn session_rolls_for_participants() {
+        let (guild, jobs, runner, lobby) = lobby_guild();
+        let participant = guild.add_member("Participant", &[runner]);
+        let latecomer = guild.add_member("Latecomer", &[runner]);
+        guild.join_voice(latecomer, lobby);
+        let session = Session {
+            started_at: Some(0),
+            participants: vec![participant.0],
+            ..Default::default()
+        };
 
-    msg.channel_id.say(&ctx.http, content).await.unwrap()
+        let players = gather_players(&guild, &session, Some(lobby), &jobs, None, &GuildQualifications::new()).await.unwrap();
+
+        assert_eq!(players.keys().collect::<Vec<_>>(), vec![&participant]);
+    }
+
+    #[tokio::test]
+    async fn counts_registered_qualifications() {
+        let (guild, jobs, runner, lobby) = lobby_guild();
+        let registered = guild.add_member("Registered", &[]);
+        guild.join_voice(registered, lobby);
+        let qualifications: GuildQualifications = vec![(registered.0, vec![runner.0])].into_iter().collect();
+
+        let players = gather_players(&guild, &Session::default(), Some(lobby), &jobs, None, &qualifications)
+            .await
+            .unwrap();
+
+        assert!(players[&registered].contains(&runner));
+    }
+
+    #[tokio::test]
+    async fn announces_pairings() {
+        let (guild, mut jobs, runner, lobby) = lobby_guild();
+        let player = guild.add_member("Player", &[runner]);
+        guild.join_voice(player, lobby);
+
+        let players = gather_players(&guild, &Session::default(), Some(lobby), &jobs, None, &GuildQualifications::new())
+            .await
+            .unwrap();
+        let assigned = decide_pairings(&mut jobs, &players, Apportionment::default());
+        let content = format_pairings(&guild.roles().await.unwrap(), Some(2), assigned);
+
+        assert_eq!(content, format!("**Round 2**\nRunner:\n- <@{}>,\n\n", player));
+    }
+
+    #[tokio::test]
+    async fn prefers_higher_tiers() {
+        let (guild, mut jobs, runner, lobby) = lobby_guild();
+        let backup = guild.add_role("Runner (backup)");
+        let pit = guild.add_role("Pit");
+        jobs.insert(
+            runner.0,
+            Job {
+                points: vec![(3, 1)].into_iter().collect(),
+                tiers: vec![backup.0],
+                ..job(&[])
+            },
+        );
+        jobs.insert(pit.0, Job { points: vec![(3, 2)].into_iter().collect(), ..job(&[]) });
+        let main = guild.add_member("Main", &[runner, pit]);
+        let spare = guild.add_member("Spare", &[backup, pit]);
+        let crew = guild.add_member("Crew", &[pit]);
+        for user_id in [main, spare, crew] {
+            guild.join_voice(user_id, lobby);
+        }
+
+        let players = gather_players(&guild, &Session::default(), Some(lobby), &jobs, None, &GuildQualifications::new())
+            .await
+            .unwrap();
+        for _ in 0..20 {
+            let assigned = decide_pairings(&mut jobs, &players, Apportionment::default()).unwrap();
+            assert_eq!(assigned[&runner], vec![main]);
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_instructions_to_assigned_players() {
+        let (guild, mut jobs, runner, _lobby) = lobby_guild();
+        jobs.get_mut(&runner.0).unwrap().instructions = Some("Start the timer.".to_owned());
+        let player = guild.add_member("Player", &[runner]);
+        let assigned = vec![(runner, vec![player])].into_iter().collect();
+
+        send_instructions(&guild, &jobs, &assigned).await;
+
+        assert_eq!(
+            guild.direct_messages(player),
+            vec!["You were assigned **Runner** in the server.\n\nStart the timer.".to_owned()]
+        );
+    }
+
+    #[tokio::test]
+    async fn announces_missing_qualifications() {
+        let (guild, _jobs, runner, _lobby) = lobby_guild();
+
+        let content = format_pairings(&guild.roles().await.unwrap(), None, Err(PairingError::Unqualified(runner)));
+
+        assert_eq!(content, "Not enough players qualified for role 'Runner'.");
+    }
+
+    #[tokio::test]
+    async fn previews_needed_and_qualified_players() {
+        let (guild, jobs, runner, lobby) = lobby_guild();
+        let excluded = guild.add_role("Excluded");
+        let player = guild.add_member("Player", &[runner]);
+        let resting = guild.add_member("Resting", &[runner, excluded]);
+        let spectator = guild.add_member("Spectator", &[]);
+        for user_id in [player, resting, spectator] {
+            guild.join_voice(user_id, lobby);
+        }
+
+        let content = preview_roll(&guild, jobs, lobby, Some(excluded.0), &GuildQualifications::new(), Apportionment::default()).await.unwrap();
+
+        assert!(content.starts_with("Preview for 1 players:\n**Runner**: 1 needed, 1 qualified\n"));
+        assert!(content.contains("Excluded: Resting\n"));
+        assert!(content.contains("Not qualified for any role: Spectator\n"));
+        assert!(content.contains("- Player: Runner 100%\n"));
+    }
+
+    #[tokio::test]
+    async fn preview_needs_roles() {
+        let (guild, _jobs, _runner, lobby) = lobby_guild();
+
+        assert_eq!(preview_roll(&guild, Jobs::new(), lobby, None, &GuildQualifications::new(), Apportionment::default()).await.unwrap(),
+            "No roles to preview."
+        );
+    }
 }
@@ -0,0 +1,202 @@
+use serenity::{model::prelude::*, prelude::*};
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+use tokio::{sync::watch, task::JoinHandle};
+
+use super::{
+    guild::{DiscordGuild, GuildAccess},
+    roll::{assign_roles, RollTarget},
+};
+
+/// Shortest allowed time between scheduled rolls.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(60);
+/// How long before a scheduled roll players are warned.
+const WARNING_BEFORE: Duration = Duration::from_secs(60);
+
+/// Task rolling in the background until it's told to stop.
+/// It only stops between rolls, so a roll never ends up half assigned.
+pub struct RollTask {
+    stop: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+impl RollTask {
+    /// Spawns the task, which stops once the receiver it's given changes.
+    pub fn spawn<F>(task: impl FnOnce(watch::Receiver<bool>) -> F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (stop, stopped) = watch::channel(false);
+        Self {
+            stop,
+            handle: tokio::spawn(task(stopped)),
+        }
+    }
+
+    /// Tells the task to stop and waits until it finished its current roll.
+    pub async fn stop(self) {
+        self.stop.send(true).ok();
+        self.handle.await.ok();
+    }
+}
+
+/// Scheduled rolls by the text channel they are announced in.
+pub struct ScheduledRolls;
+
+impl TypeMapKey for ScheduledRolls {
+    type Value = Arc<Mutex<HashMap<ChannelId, RollTask>>>;
+}
+
+/// Starts rolling periodically, replacing a previous schedule in the same channel.
+pub async fn schedule_rolls(ctx: &Context, target: RollTarget, interval: Duration) {
+    let task_ctx = ctx.clone();
+    let task = RollTask::spawn(move |stop| async move {
+        let guild = DiscordGuild::new(&task_ctx, target.guild_id);
+        roll_periodically(&guild, target.channel_id, interval, stop, || async {
+            if let Err(why) = assign_roles(&task_ctx, target).await {
+                tracing::error!(guild = target.guild_id.0, error = %why, "scheduled roll failed");
+            }
+        })
+        .await;
+    });
+
+    let scheduled = scheduled_rolls(ctx).await;
+    let previous = scheduled.lock().await.insert(target.channel_id, task);
+    if let Some(previous) = previous {
+        previous.stop().await;
+    }
+}
+
+/// Rolls after every interval until told to stop, which only interrupts the wait.
+async fn roll_periodically<F: Future<Output = ()>>(
+    guild: &impl GuildAccess,
+    channel_id: ChannelId,
+    interval: Duration,
+    mut stop: watch::Receiver<bool>,
+    mut roll: impl FnMut() -> F,
+) {
+    loop {
+        tokio::select! {
+            _ = wait_for_roll(guild, channel_id, interval) => {}
+            _ = stop.changed() => return,
+        }
+        roll().await;
+    }
+}
+
+/// Waits until the next roll, warning players in the channel before it.
+async fn wait_for_roll(guild: &impl GuildAccess, channel_id: ChannelId, interval: Duration) {
+    let (before_warning, after_warning) = split_at_warning(interval);
+    tokio::time::sleep(before_warning).await;
+    guild.say(channel_id, "Rerolling in 1 minute.").await.ok();
+    tokio::time::sleep(after_warning).await;
+}
+
+/// Splits the interval around the warning, which is sent right away for short intervals.
+fn split_at_warning(interval: Duration) -> (Duration, Duration) {
+    let after_warning = interval.min(WARNING_BEFORE);
+    (interval - after_warning, after_warning)
+}
+
+/// Stops rolling periodically in a channel, returns whether rolls were scheduled.
+pub async fn cancel_rolls(ctx: &Context, channel_id: ChannelId) -> bool {
+    let scheduled = scheduled_rolls(ctx).await;
+    let removed = scheduled.lock().await.remove(&channel_id);
+    match removed {
+        Some(task) => {
+            task.stop().await;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stops all scheduled rolls and waits until none of them is running.
+pub async fn cancel_all_rolls(data: &RwLock<TypeMap>) {
+    let scheduled = match data.read().await.get::<ScheduledRolls>().cloned() {
+        Some(scheduled) => scheduled,
+        None => return,
+    };
+    let tasks: Vec<RollTask> = scheduled.lock().await.drain().map(|(_channel_id, task)| task).collect();
+    for task in tasks {
+        task.stop().await;
+    }
+}
+
+async fn scheduled_rolls(ctx: &Context) -> Arc<Mutex<HashMap<ChannelId, RollTask>>> {
+    ctx.data
+        .read()
+        .await
+        .get::<ScheduledRolls>()
+        .cloned()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::FakeGuild;
+    use tokio::sync::Notify;
+
+    fn scheduled_data(channel_id: ChannelId, task: RollTask) -> RwLock<TypeMap> {
+        let scheduled: Arc<Mutex<HashMap<ChannelId, RollTask>>> = Arc::default();
+        scheduled.try_lock().unwrap().insert(channel_id, task);
+        let mut data = TypeMap::new();
+        data.insert::<ScheduledRolls>(scheduled);
+        RwLock::new(data)
+    }
+
+    #[test]
+    fn warns_before_every_roll() {
+        let minute = Duration::from_secs(60);
+
+        assert_eq!(split_at_warning(5 * minute), (4 * minute, minute));
+        assert_eq!(split_at_warning(MIN_INTERVAL), (Duration::ZERO, MIN_INTERVAL));
+    }
+
+    #[tokio::test]
+    async fn warns_in_the_channel() {
+        let guild = FakeGuild::default();
+        let channel_id = ChannelId(1);
+
+        wait_for_roll(&guild, channel_id, Duration::from_millis(10)).await;
+
+        assert_eq!(guild.messages(channel_id), vec!["Rerolling in 1 minute.".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn cancels_every_schedule() {
+        let task = RollTask::spawn(|mut stop| async move {
+            stop.changed().await.ok();
+        });
+        let data = scheduled_data(ChannelId(1), task);
+
+        cancel_all_rolls(&data).await;
+
+        let scheduled = data.read().await.get::<ScheduledRolls>().cloned().unwrap();
+        assert!(scheduled.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn finishes_roll_in_flight_when_cancelled() {
+        let rolls: Arc<std::sync::Mutex<Vec<&str>>> = Arc::default();
+        let started = Arc::new(Notify::new());
+        let task_rolls = rolls.clone();
+        let task_started = started.clone();
+        let task = RollTask::spawn(move |stop| async move {
+            let guild = FakeGuild::default();
+            roll_periodically(&guild, ChannelId(1), Duration::from_millis(10), stop, || async {
+                task_rolls.lock().unwrap().push("started");
+                task_started.notify_one();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                task_rolls.lock().unwrap().push("finished");
+            })
+            .await;
+        });
+        let data = scheduled_data(ChannelId(1), task);
+
+        started.notified().await;
+        cancel_all_rolls(&data).await;
+
+        assert_eq!(*rolls.lock().unwrap(), vec!["started", "finished"]);
+    }
+}
@@ -0,0 +1,382 @@
+use crate::{
+    error::BotError,
+    model::{load_session, lock_session, save_session, Job, Round, Session},
+};
+use indexmap::IndexMap;
+use serenity::{
+    framework::standard::{macros::command, Args, CommandResult},
+    model::prelude::*,
+    prelude::*,
+};
+use std::{collections::HashMap, fmt::Write};
+
+use super::{
+    exclude::release_session_exclusions,
+    guild::{DiscordGuild, GuildAccess},
+    util::{get_callers_vc, role_name, unix_now, Reply},
+};
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(start, end, status)]
+#[description("Management of the ongoing session.")]
+async fn session(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    msg.channel_id
+        .say(ctx, "Invalid subcommand.".to_owned())
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Start a session with all players in the caller's voice channel. Rolls during the session only include these players and are numbered as rounds.")]
+async fn start(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let _lock = lock_session(guild_id.0).await;
+    let mut session = load_session(guild_id.0);
+    let content = start_session(&guild, &mut session, msg.author.id).await?;
+    save_session(guild_id.0, session);
+
+    guild.say(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Starts a session with all players in the caller's voice channel.
+async fn start_session(guild: &impl GuildAccess, session: &mut Session, caller: UserId) -> Reply {
+    if session.is_active() {
+        return Err(BotError::rejected("Session is already running."));
+    }
+
+    let voice_channel_id = match get_callers_vc(guild, caller).await {
+        Some(voice_channel_id) => voice_channel_id,
+        None => return Err(BotError::NotInVoiceChannel),
+    };
+    session.started_at = Some(unix_now());
+    session.participants = guild
+        .voice_members(voice_channel_id)
+        .await
+        .into_iter()
+        .map(|user_id| user_id.0)
+        .collect();
+    session.rounds.clear();
+    Ok(format!("Session started with {} players.", session.participants.len()))
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("End the session, removing session roles from all players, moving them back to the original voice channel and summarizing who played what.")]
+async fn end(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let _lock = lock_session(guild_id.0).await;
+    let mut session = load_session(guild_id.0);
+    let ended = end_session(&guild, &mut session).await;
+    save_session(guild_id.0, session);
+    release_session_exclusions(&guild).await;
+
+    guild.say_quietly(msg.channel_id, &ended?).await?;
+    Ok(())
+}
+
+/// Takes back what the session handed out and summarizes it.
+async fn end_session(guild: &impl GuildAccess, session: &mut Session) -> Reply {
+    revoke_session_roles(guild, session).await;
+    regroup_players(guild, session).await;
+
+    let content = match session.is_active() {
+        true => summarize(session, &guild.roles().await?),
+        false => "Session ended.".to_owned(),
+    };
+    session.started_at = None;
+    session.participants.clear();
+    session.rounds.clear();
+    Ok(content)
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Show the participants and rounds of the ongoing session.")]
+async fn status(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let session = load_session(guild_id.0);
+    let started_at = match session.started_at {
+        Some(started_at) => started_at,
+        None => {
+            msg.channel_id.say(ctx, "No session is running.").await?;
+            return Ok(());
+        }
+    };
+
+    let mut content = String::new();
+    content
+        .write_fmt(format_args!(
+            "Session started <t:{}:R> with {} rounds so far.\n",
+            started_at,
+            session.rounds.len()
+        ))
+        .ok();
+    content.write_str("Participants:\n").ok();
+    for user_id in session.participants.iter() {
+        content.write_fmt(format_args!("- <@{}>,\n", user_id)).ok();
+    }
+
+    DiscordGuild::new(ctx, guild_id).say_quietly(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Move all players from role voice channels back to the voice channel they were rolled in.")]
+async fn regroup(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let _lock = lock_session(guild_id.0).await;
+    let mut session = load_session(guild_id.0);
+    let moved = regroup_players(&guild, &mut session).await;
+    save_session(guild_id.0, session);
+
+    let content = match moved {
+        0 => "No players to regroup.".to_owned(),
+        moved => format!("Moved {} players back.", moved),
+    };
+    guild.say(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Records the assignment as the next round of the session.
+pub fn record_round(session: &mut Session, assigned: &IndexMap<RoleId, Vec<UserId>>) {
+    session.rounds.push(Round {
+        rolled_at: unix_now(),
+        assigned: assigned
+            .iter()
+            .map(|(role_id, players)| (role_id.0, players.iter().map(|user_id| user_id.0).collect()))
+            .collect(),
+    });
+}
+
+/// Describes how many rounds were played and who played what.
+fn summarize(session: &Session, roles: &HashMap<RoleId, String>) -> String {
+    let mut played: IndexMap<u64, IndexMap<u64, u32>> = session
+        .participants
+        .iter()
+        .map(|user_id| (*user_id, IndexMap::new()))
+        .collect();
+    for round in session.rounds.iter() {
+        for (role_id, players) in round.assigned.iter() {
+            for user_id in players {
+                let counts = played.entry(*user_id).or_default();
+                *counts.entry(*role_id).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut content = String::new();
+    content
+        .write_fmt(format_args!(
+            "Session ended after {} rounds.\n",
+            session.rounds.len()
+        ))
+        .ok();
+    for (user_id, mut counts) in played {
+        counts.sort_by(|_role_id_a, a, _role_id_b, b| b.cmp(a));
+        let counts: Vec<String> = counts
+            .into_iter()
+            .map(|(role_id, count)| format!("{} ×{}", role_name(roles, RoleId(role_id)), count))
+            .collect();
+        match counts.is_empty() {
+            true => content.write_fmt(format_args!("- <@{}>: nothing,\n", user_id)).ok(),
+            false => content
+                .write_fmt(format_args!("- <@{}>: {},\n", user_id, counts.join(", ")))
+                .ok(),
+        };
+    }
+    content
+}
+
+/// Grants session roles of assigned jobs, replacing the ones from the previous roll.
+pub async fn grant_session_roles(
+    guild: &impl GuildAccess,
+    session: &mut Session,
+    jobs: &HashMap<u64, Job>,
+    assigned: &IndexMap<RoleId, Vec<UserId>>,
+) -> Result<(), BotError> {
+    revoke_session_roles(guild, session).await;
+
+    let mut failure = None;
+    for (role_id, players) in assigned {
+        let session_role = match jobs.get(&role_id.0).and_then(|job| job.session_role) {
+            Some(session_role) => session_role,
+            None => continue,
+        };
+        for user_id in players {
+            match guild.add_member_role(*user_id, RoleId(session_role)).await {
+                Ok(()) => session.granted_roles.push((user_id.0, session_role)),
+                Err(why) => failure = failure.or(Some(why)),
+            }
+        }
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+/// Removes all granted session roles.
+pub async fn revoke_session_roles(guild: &impl GuildAccess, session: &mut Session) {
+    for (user_id, role_id) in session.granted_roles.drain(..) {
+        guild
+            .remove_member_role(UserId(user_id), RoleId(role_id))
+            .await
+            .ok();
+    }
+}
+
+/// Moves assigned players into their job's voice channel.
+pub async fn split_voice_channels(
+    guild: &impl GuildAccess,
+    session: &mut Session,
+    origin_channel: ChannelId,
+    jobs: &HashMap<u64, Job>,
+    assigned: &IndexMap<RoleId, Vec<UserId>>,
+) {
+    let mut moved_players = Vec::new();
+    for (role_id, players) in assigned {
+        let voice_channel = match jobs.get(&role_id.0).and_then(|job| job.voice_channel) {
+            Some(voice_channel) => voice_channel,
+            None => continue,
+        };
+        for user_id in players {
+            if guild
+                .move_member(*user_id, ChannelId(voice_channel))
+                .await
+                .is_ok()
+            {
+                moved_players.push(user_id.0);
+            }
+        }
+    }
+    if moved_players.is_empty() {
+        return;
+    }
+    session.origin_channel = Some(origin_channel.0);
+    session.moved_players.retain(|user_id| !moved_players.contains(user_id));
+    session.moved_players.extend(moved_players);
+}
+
+/// Moves players from job voice channels back to the original one,
+/// returns the amount of moved players.
+pub async fn regroup_players(guild: &impl GuildAccess, session: &mut Session) -> usize {
+    let origin_channel = match session.origin_channel.take() {
+        Some(origin_channel) => origin_channel,
+        None => return 0,
+    };
+    let mut moved = 0;
+    for user_id in session.moved_players.drain(..) {
+        if guild
+            .move_member(UserId(user_id), ChannelId(origin_channel))
+            .await
+            .is_ok()
+        {
+            moved += 1;
+        }
+    }
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::runner_guild;
+
+    #[tokio::test]
+    async fn starts_with_players_in_callers_channel() {
+        let (guild, _jobs, _runner) = runner_guild(&[]);
+        let lobby = guild.add_voice_channel("Lobby");
+        let caller = guild.add_member("Caller", &[]);
+        let friend = guild.add_member("Friend", &[]);
+        let elsewhere = guild.add_member("Elsewhere", &[]);
+        guild.join_voice(caller, lobby);
+        guild.join_voice(friend, lobby);
+        let mut session = Session::default();
+
+        let reply = start_session(&guild, &mut session, elsewhere).await;
+        assert!(matches!(reply, Err(BotError::NotInVoiceChannel)));
+
+        let reply = start_session(&guild, &mut session, caller).await;
+        assert_eq!(reply.unwrap(), "Session started with 2 players.");
+        assert!(session.is_active());
+        assert!(session.participants.contains(&caller.0) && session.participants.contains(&friend.0));
+
+        let reply = start_session(&guild, &mut session, caller).await;
+        assert_eq!(reply.unwrap_err().to_string(), "Session is already running.");
+    }
+
+    #[tokio::test]
+    async fn replaces_session_roles() {
+        let (guild, mut jobs, runner) = runner_guild(&[]);
+        let running = guild.add_role("Running");
+        jobs.get_mut(&runner.0).unwrap().session_role = Some(running.0);
+        let first = guild.add_member("First", &[runner]);
+        let second = guild.add_member("Second", &[runner]);
+        let mut session = Session::default();
+
+        let assigned = vec![(runner, vec![first])].into_iter().collect();
+        grant_session_roles(&guild, &mut session, &jobs, &assigned).await.unwrap();
+        assert!(guild.has_role(first, running));
+
+        let assigned = vec![(runner, vec![second])].into_iter().collect();
+        grant_session_roles(&guild, &mut session, &jobs, &assigned).await.unwrap();
+        assert!(!guild.has_role(first, running));
+        assert!(guild.has_role(second, running));
+
+        revoke_session_roles(&guild, &mut session).await;
+        assert!(!guild.has_role(second, running));
+        assert!(session.granted_roles.is_empty());
+    }
+
+    #[tokio::test]
+    async fn splits_and_regroups_players() {
+        let (guild, mut jobs, runner) = runner_guild(&[]);
+        let lobby = guild.add_voice_channel("Lobby");
+        let track = guild.add_voice_channel("Track");
+        jobs.get_mut(&runner.0).unwrap().voice_channel = Some(track.0);
+        let player = guild.add_member("Player", &[runner]);
+        let disconnected = guild.add_member("Disconnected", &[runner]);
+        guild.join_voice(player, lobby);
+        let mut session = Session::default();
+
+        let assigned = vec![(runner, vec![player, disconnected])].into_iter().collect();
+        split_voice_channels(&guild, &mut session, lobby, &jobs, &assigned).await;
+        assert_eq!(guild.voice_channel(player).await, Some(track));
+        assert_eq!(session.moved_players, vec![player.0]);
+
+        assert_eq!(regroup_players(&guild, &mut session).await, 1);
+        assert_eq!(guild.voice_channel(player).await, Some(lobby));
+        assert_eq!(regroup_players(&guild, &mut session).await, 0);
+    }
+
+    #[tokio::test]
+    async fn ending_summarizes_and_clears_the_session() {
+        let (guild, _jobs, runner) = runner_guild(&[]);
+        let player = guild.add_member("Player", &[runner]);
+        let idle = guild.add_member("Idle", &[]);
+        let mut session = Session {
+            started_at: Some(0),
+            participants: vec![player.0, idle.0],
+            rounds: vec![Round {
+                rolled_at: 0,
+                assigned: vec![(runner.0, vec![player.0])].into_iter().collect(),
+            }],
+            ..Session::default()
+        };
+
+        let content = end_session(&guild, &mut session).await.unwrap();
+
+        assert_eq!(
+            content,
+            format!("Session ended after 1 rounds.\n- <@{}>: Runner ×1,\n- <@{}>: nothing,\n", player, idle)
+        );
+        assert!(!session.is_active());
+        assert!(session.participants.is_empty() && session.rounds.is_empty());
+        assert_eq!(end_session(&guild, &mut session).await.unwrap(), "Session ended.");
+    }
+}
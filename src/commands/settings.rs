@@ -0,0 +1,322 @@
+use crate::{
+    config,
+    error::BotError,
+    model::{load_guild_config, load_guild_configs, update_guild_config, GuildConfig, GuildConfigs},
+};
+use role_dispatch_core::Apportionment;
+use serenity::{
+    framework::standard::{macros::command, Args, CommandResult},
+    model::prelude::*,
+    prelude::*,
+};
+use std::{collections::HashMap, fmt::Write, sync::Arc};
+
+use super::{
+    checks::MANAGER_CHECK,
+    guild::{DiscordGuild, GuildAccess},
+    util::{parse_switch, role_by_name, role_name, Reply},
+};
+
+/// Prefixes of guilds which set their own, so messages don't reread the configuration.
+pub struct GuildPrefixes;
+
+impl TypeMapKey for GuildPrefixes {
+    type Value = Arc<RwLock<HashMap<GuildId, String>>>;
+}
+
+/// Loads the prefixes guilds set for the cache.
+pub fn load_guild_prefixes() -> HashMap<GuildId, String> {
+    guild_prefixes(load_guild_configs())
+}
+
+fn guild_prefixes(guild_configs: GuildConfigs) -> HashMap<GuildId, String> {
+    guild_configs
+        .into_iter()
+        .filter_map(|(guild_id, guild_config)| Some((GuildId(guild_id), guild_config.prefix?)))
+        .collect()
+}
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(manager, dm, prefix, apportionment, mirror)]
+#[description("Show the configuration of this server.")]
+async fn settings(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    if !args.is_empty() {
+        return Err(BotError::rejected("Invalid subcommand.").into());
+    }
+
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let content = show_settings(&guild, &load_guild_config(guild_id.0)).await?;
+    guild.say(msg.channel_id, &content).await?;
+
+    Ok(())
+}
+
+async fn show_settings(guild: &impl GuildAccess, guild_config: &GuildConfig) -> Reply {
+    let roles = guild.roles().await?;
+    let mut content = String::new();
+    content.write_str("Settings:\n").ok();
+    match guild_config.manager_role {
+        Some(role_id) => content
+            .write_fmt(format_args!("**Manager role**: {}\n", role_name(&roles, RoleId(role_id))))
+            .ok(),
+        None => content.write_str("**Manager role**: none\n").ok(),
+    };
+    content
+        .write_fmt(format_args!(
+            "**Direct messages**: {}\n",
+            if guild_config.direct_messages { "on" } else { "off" }
+        ))
+        .ok();
+    let prefix = guild_config.prefix.as_ref().unwrap_or(&config::get().prefix);
+    content.write_fmt(format_args!("**Prefix**: `{}`\n", prefix)).ok();
+    content
+        .write_fmt(format_args!("**Apportionment**: `{}`\n", guild_config.apportionment))
+        .ok();
+    content
+        .write_fmt(format_args!(
+            "**Mirror qualifications**: {}\n",
+            if guild_config.mirror_qualifications { "on" } else { "off" }
+        ))
+        .ok();
+    Ok(content)
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Set the role allowed to change the configuration by specifying it's name. Leave empty to only allow members with the Administrator or Manage Server permission.")]
+async fn manager(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() > 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let roles = guild.roles().await?;
+    let name = args.single::<String>().ok();
+    let content = update_guild_config(guild_id.0, |guild_config| set_manager(&roles, guild_config, name))?;
+
+    guild.say(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Sets the manager role by name or clears it.
+fn set_manager(roles: &HashMap<RoleId, String>, guild_config: &mut GuildConfig, name: Option<String>) -> Reply {
+    match name {
+        None => {
+            guild_config.manager_role = None;
+            Ok("Manager role cleared.".to_owned())
+        }
+        Some(name) => match role_by_name(roles, &name) {
+            Some(role_id) => {
+                guild_config.manager_role = Some(role_id.0);
+                Ok(format!("Manager role set to '{}'.", name))
+            }
+            None => Err(BotError::rejected("Role doesn't exist.")),
+        },
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Turn `on` or `off` sending every assigned player their role and its instructions in a direct message.")]
+async fn dm(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() != 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let enabled = parse_switch(&args.single::<String>().unwrap())?;
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    update_guild_config(guild_id.0, |guild_config| guild_config.direct_messages = enabled);
+
+    let content = match enabled {
+        true => "Assigned players will receive direct messages.",
+        false => "Assigned players won't receive direct messages.",
+    };
+    msg.channel_id.say(ctx, content.to_owned()).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Turn `on` or `off` granting and removing the Discord role when a qualification is registered or removed with `qualify` and `unqualify`.")]
+async fn mirror(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() != 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let enabled = parse_switch(&args.single::<String>().unwrap())?;
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    update_guild_config(guild_id.0, |guild_config| guild_config.mirror_qualifications = enabled);
+
+    let content = match enabled {
+        true => "Registered qualifications will be mirrored to Discord roles.",
+        false => "Registered qualifications won't be mirrored to Discord roles.",
+    };
+    msg.channel_id.say(ctx, content.to_owned()).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Set the prefix of commands in this server. Leave empty to use the default prefix.")]
+async fn prefix(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() > 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let (content, prefix) = update_guild_config(guild_id.0, |guild_config| {
+        let content = set_prefix(guild_config, args.single::<String>().ok());
+        (content, guild_config.prefix.clone())
+    });
+    let content = content?;
+
+    let prefixes = ctx.data.read().await.get::<GuildPrefixes>().cloned();
+    if let Some(prefixes) = prefixes {
+        let mut prefixes = prefixes.write().await;
+        match prefix {
+            Some(prefix) => prefixes.insert(guild_id, prefix),
+            None => prefixes.remove(&guild_id),
+        };
+    }
+
+    msg.channel_id.say(ctx, content).await?;
+    Ok(())
+}
+
+fn set_prefix(guild_config: &mut GuildConfig, prefix: Option<String>) -> Reply {
+    match prefix {
+        None => {
+            guild_config.prefix = None;
+            Ok(format!("Prefix reset to `{}`.", config::get().prefix))
+        }
+        Some(prefix) if prefix.is_empty() || prefix.contains(char::is_whitespace) => {
+            Err(BotError::rejected("Prefix can't be empty or contain spaces."))
+        }
+        Some(prefix) => {
+            let content = format!("Prefix set to `{}`.", prefix);
+            guild_config.prefix = Some(prefix);
+            Ok(content)
+        }
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(Manager)]
+#[description("Set how players left over after whole shares are split between roles: `largest-remainder` (default), `dhondt`, `sainte-lague` or `weighted-priority`.")]
+async fn apportionment(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() != 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let name = args.single::<String>().unwrap();
+    let content = update_guild_config(guild_id.0, |guild_config| set_apportionment(guild_config, &name))?;
+
+    msg.channel_id.say(ctx, content).await?;
+    Ok(())
+}
+
+fn set_apportionment(guild_config: &mut GuildConfig, name: &str) -> Reply {
+    match name.parse::<Apportionment>() {
+        Ok(method) => {
+            guild_config.apportionment = method;
+            Ok(format!("Apportionment set to `{}`.", method))
+        }
+        Err(()) => {
+            let names: Vec<&str> = Apportionment::ALL.iter().map(|method| method.name()).collect();
+            Err(BotError::Rejected(format!("Unknown apportionment, expected one of: {}.", names.join(", "))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::FakeGuild;
+
+    #[tokio::test]
+    async fn shows_manager_role() {
+        let guild = FakeGuild::default();
+        let organizer = guild.add_role("Organizer");
+        let guild_config = GuildConfig {
+            manager_role: Some(organizer.0),
+            ..Default::default()
+        };
+
+        let content = show_settings(&guild, &guild_config).await.unwrap();
+
+        assert!(content.contains("**Manager role**: Organizer"));
+        assert!(content.contains("**Direct messages**: off"));
+    }
+
+    #[tokio::test]
+    async fn manager_role_must_exist() {
+        let guild = FakeGuild::default();
+        let organizer = guild.add_role("Organizer");
+        let roles = guild.roles().await.unwrap();
+        let mut guild_config = GuildConfig::default();
+
+        let reply = set_manager(&roles, &mut guild_config, Some("Host".to_owned()));
+        assert_eq!(reply.unwrap_err().to_string(), "Role doesn't exist.");
+        assert_eq!(guild_config.manager_role, None);
+
+        let reply = set_manager(&roles, &mut guild_config, Some("Organizer".to_owned()));
+        assert!(reply.is_ok());
+        assert_eq!(guild_config.manager_role, Some(organizer.0));
+
+        assert!(set_manager(&roles, &mut guild_config, None).is_ok());
+        assert_eq!(guild_config.manager_role, None);
+    }
+
+    #[test]
+    fn prefix_overrides_default() {
+        let mut guild_config = GuildConfig::default();
+
+        assert_eq!(set_prefix(&mut guild_config, Some("?".to_owned())).unwrap(), "Prefix set to `?`.");
+        assert_eq!(guild_config.prefix.as_deref(), Some("?"));
+
+        assert!(set_prefix(&mut guild_config, Some("a b".to_owned())).is_err());
+        assert_eq!(guild_config.prefix.as_deref(), Some("?"));
+
+        assert_eq!(set_prefix(&mut guild_config, None).unwrap(), "Prefix reset to `!`.");
+        assert_eq!(guild_config.prefix, None);
+    }
+
+    #[test]
+    fn caches_only_set_prefixes() {
+        let mut guild_configs = GuildConfigs::new();
+        guild_configs.insert(1, GuildConfig { prefix: Some("?".to_owned()), ..GuildConfig::default() });
+        guild_configs.insert(2, GuildConfig::default());
+
+        let prefixes = guild_prefixes(guild_configs);
+
+        assert_eq!(prefixes.len(), 1);
+        assert_eq!(prefixes[&GuildId(1)], "?");
+    }
+
+    #[test]
+    fn apportionment_must_be_known() {
+        let mut guild_config = GuildConfig::default();
+
+        assert!(set_apportionment(&mut guild_config, "fair").is_err());
+        assert_eq!(guild_config.apportionment, Apportionment::LargestRemainder);
+
+        assert_eq!(set_apportionment(&mut guild_config, "dhondt").unwrap(), "Apportionment set to `dhondt`.");
+        assert_eq!(guild_config.apportionment, Apportionment::DHondt);
+    }
+}
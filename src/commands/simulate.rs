@@ -1,4 +1,9 @@
-use crate::model::{Job, load_jobs};
+use crate::{
+    error::BotError,
+    model::{Job, Jobs, load_guild_config, load_jobs},
+};
+use rand::Rng;
+use role_dispatch_core::{Apportionment, PairingError};
 use serenity::{
     framework::standard::{
         macros::command,
@@ -10,68 +15,449 @@ use serenity::{
 use std::{
     collections::{HashMap, HashSet},
     fmt::Write,
+    ops::RangeInclusive,
+};
+
+use super::{
+    guild::{DiscordGuild, GuildAccess},
+    roll::{decide_pairings, describe_failure},
+    util::{remove_irrelevant_qualifications, role_name, Reply},
 };
 
-use super::{roll::decide_pairings};
+/// Maximum amount of player counts simulated at once.
+const MAX_RANGE_LEN: usize = 16;
+/// Amount of Monte Carlo trials when none is specified.
+const DEFAULT_TRIALS: u32 = 1000;
+/// Maximum amount of Monte Carlo trials per player count.
+const MAX_TRIALS: u32 = 10000;
 
 #[command]
 #[aliases("sim")]
 #[only_in(guilds)]
-#[description("Simulate how many of each role will be required for specific player count.")]
+#[sub_commands(montecarlo)]
+#[description("Simulate how many of each role will be required for specific player count or a range of player counts like `4-12`.")]
 async fn simulate(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     if args.len() != 1 {
-        msg.channel_id
-            .say(ctx, "Invalid amount of arguments.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
     }
 
     args.trimmed().quoted();
-    let result = args.single::<u16>();
+    let result = args.single::<String>().ok().and_then(|arg| parse_player_counts(&arg));
 
-    if let Err(_) = result {
-        msg.channel_id
-            .say(ctx, "Invalid player count.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+    if result.is_none() {
+        return Err(BotError::rejected("Invalid player count.").into());
     }
 
-    let player_count = result.unwrap();
-    let mut jobs = load_jobs();
-    let users_roles = simulate_roles(&jobs, player_count);
-    let mut assigned = decide_pairings(&mut jobs, &users_roles).unwrap();
-    let roles = msg.guild_id.unwrap().roles(&ctx.http).await.unwrap();
+    let player_counts = result.unwrap();
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let method = load_guild_config(guild_id.0).apportionment;
+    let content = simulate_counts(&guild, &mut load_jobs(), player_counts, method).await?;
+
+    guild.say(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Describes how many players each job gets for every player count.
+async fn simulate_counts(
+    guild: &impl GuildAccess,
+    jobs: &mut Jobs,
+    player_counts: RangeInclusive<u16>,
+    method: Apportionment,
+) -> Reply {
+    if let Some(reason) = unsimulatable(jobs) {
+        return Err(BotError::rejected(reason));
+    }
+    let roles = guild.roles().await?;
 
     let mut content = String::new();
-    content.write_fmt(format_args!("Simulation for {} players:\n", player_count)).ok();
-    assigned.sort_keys();
-    assigned.into_iter().for_each(|(role_id, players)| {
-        content
-            .write_fmt(format_args!(
-                "**{}**: {}\n",
-                roles.get(&role_id).unwrap().name,
-                players.len()
-            ))
-            .ok();
-    });
-
-    msg.channel_id
-        .say(ctx, content)
-        .await
-        .ok();
+    if player_counts.start() == player_counts.end() {
+        let player_count = *player_counts.start();
+        let users_roles = simulate_roles(jobs, player_count);
+        let mut assigned = match decide_pairings(jobs, &users_roles, method) {
+            Ok(assigned) => assigned,
+            Err(why) => {
+                let reason = describe_failure(why, |role_id| format!("'{}'", role_name(&roles, role_id)));
+                return Err(BotError::Rejected(reason));
+            }
+        };
+        content.write_fmt(format_args!("Simulation for {} players:\n", player_count)).ok();
+        assigned.sort_keys();
+        assigned.into_iter().for_each(|(role_id, players)| {
+            content
+                .write_fmt(format_args!(
+                    "**{}**: {}\n",
+                    role_name(&roles, role_id),
+                    players.len()
+                ))
+                .ok();
+        });
+    } else {
+        let mut columns = Vec::new();
+        let mut failures = Vec::new();
+        for player_count in player_counts.clone() {
+            let users_roles = simulate_roles(jobs, player_count);
+            let column = match decide_pairings(jobs, &users_roles, method) {
+                Ok(assigned) => assigned
+                    .into_iter()
+                    .map(|(role_id, players)| (role_id, players.len().to_string()))
+                    .collect::<HashMap<RoleId, String>>(),
+                Err(why) => {
+                    let reason = describe_failure(why, |role_id| format!("'{}'", role_name(&roles, role_id)));
+                    failures.push(format!("{} players: {}", player_count, reason));
+                    jobs.keys().map(|role_id| (RoleId(*role_id), "-".to_owned())).collect()
+                }
+            };
+            columns.push(column);
+        }
+        let header = player_counts.map(|player_count| player_count.to_string()).collect();
+        let rows = sorted_role_ids(jobs)
+            .into_iter()
+            .map(|role_id| {
+                let cells = columns
+                    .iter()
+                    .map(|column| column.get(&role_id).cloned().unwrap_or_else(|| "0".to_owned()))
+                    .collect();
+                (role_name(&roles, role_id), cells)
+            })
+            .collect();
+        content.write_str("Simulation for player counts:\n").ok();
+        content.write_str(&format_table(header, rows)).ok();
+        if !failures.is_empty() {
+            content.write_fmt(format_args!("\n{}", failures.join("\n"))).ok();
+        }
+    }
+    Ok(content)
+}
+
+#[command]
+#[aliases("mc")]
+#[only_in(guilds)]
+#[description("Repeat the simulation many times with players qualified only for some roles. Specify the player count or range, followed by the chance in percent for a player to be qualified for each role and optionally the amount of trials. Shows the average amount of each role with its standard deviation and how often the assignment failed.")]
+async fn montecarlo(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let argc = args.len();
+    if argc != 2 && argc != 3 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
 
+    args.trimmed().quoted();
+    let player_counts = args.single::<String>().ok().and_then(|arg| parse_player_counts(&arg));
+    let chance = args
+        .single::<String>()
+        .ok()
+        .and_then(|arg| arg.trim_end_matches('%').parse::<f64>().ok())
+        .filter(|chance| (0. ..=100.).contains(chance));
+    let trials = match argc {
+        3 => args.single::<u32>().ok().filter(|trials| (1..=MAX_TRIALS).contains(trials)),
+        _ => Some(DEFAULT_TRIALS),
+    };
+    let (player_counts, chance, trials) = match (player_counts, chance, trials) {
+        (Some(player_counts), Some(chance), Some(trials)) => (player_counts, chance / 100., trials),
+        (None, _, _) => {
+            return Err(BotError::rejected("Invalid player count.").into());
+        }
+        (_, None, _) => {
+            return Err(BotError::rejected("Invalid qualification chance.").into());
+        }
+        (_, _, None) => {
+            return Err(BotError::rejected(format!("Invalid amount of trials, expected 1 to {}.", MAX_TRIALS)).into());
+        }
+    };
+
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let method = load_guild_config(guild_id.0).apportionment;
+    let content = simulate_trials(&guild, load_jobs(), player_counts, chance, trials, method).await?;
+
+    guild.say(msg.channel_id, &content).await?;
     Ok(())
 }
 
-fn simulate_roles(jobs: &HashMap<u64, Job>, count: u16) -> HashMap<UserId, HashSet<RoleId>> {
+/// Describes the outcome of many assignments with randomly qualified players for every player count.
+async fn simulate_trials(
+    guild: &impl GuildAccess,
+    jobs: Jobs,
+    player_counts: RangeInclusive<u16>,
+    chance: f64,
+    trials: u32,
+    method: Apportionment,
+) -> Reply {
+    if let Some(reason) = unsimulatable(&jobs) {
+        return Err(BotError::rejected(reason));
+    }
+    let role_ids = sorted_role_ids(&jobs);
+    let roles = guild.roles().await?;
+
+    let counts = player_counts.clone();
+    let all_stats = tokio::task::spawn_blocking(move || {
+        let mut jobs = jobs;
+        counts
+            .map(|player_count| run_trials(&mut jobs, player_count, chance, trials, method))
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|why| {
+        tracing::error!(error = %why, "simulation panicked");
+        BotError::rejected("Simulation failed, check the stages of every role.")
+    })?;
+
+    let header = player_counts.clone().map(|player_count| player_count.to_string()).collect();
+    let mut rows: Vec<(String, Vec<String>)> = role_ids
+        .iter()
+        .map(|role_id| {
+            let cells = all_stats
+                .iter()
+                .map(|stats| {
+                    let (mean, deviation) = stats.mean_and_deviation(*role_id);
+                    format!("{:.1}±{:.1}", mean, deviation)
+                })
+                .collect();
+            (role_name(&roles, *role_id), cells)
+        })
+        .collect();
+    rows.push((
+        "Failed".to_owned(),
+        all_stats
+            .iter()
+            .map(|stats| format!("{:.1}%", stats.failure_rate() * 100.))
+            .collect(),
+    ));
+
+    let mut content = String::new();
+    content
+        .write_fmt(format_args!(
+            "Monte Carlo simulation with {:.0}% qualification chance over {} trials:\n",
+            chance * 100.,
+            trials
+        ))
+        .ok();
+    content.write_str(&format_table(header, rows)).ok();
+    for (player_count, stats) in player_counts.zip(all_stats.iter()) {
+        if let Some((role_id, failures)) = stats.most_failed() {
+            content
+                .write_fmt(format_args!(
+                    "{} players most often lacked '{}' ({} failures).\n",
+                    player_count,
+                    role_name(&roles, role_id),
+                    failures
+                ))
+                .ok();
+        }
+    }
+    Ok(content)
+}
+
+/// Outcomes of repeated assignments for a single player count.
+struct TrialStats {
+    trials: u32,
+    failures: u32,
+    failed_roles: HashMap<RoleId, u32>,
+    /// Sum and sum of squares of the amount of players assigned to each role.
+    sums: HashMap<RoleId, (f64, f64)>,
+}
+
+impl TrialStats {
+    fn failure_rate(&self) -> f64 {
+        self.failures as f64 / self.trials as f64
+    }
+
+    /// Mean and standard deviation of a role's assigned players over successful trials.
+    fn mean_and_deviation(&self, role_id: RoleId) -> (f64, f64) {
+        let successes = (self.trials - self.failures) as f64;
+        match self.sums.get(&role_id) {
+            Some((sum, squares)) if successes > 0. => {
+                let mean = sum / successes;
+                let variance = (squares / successes - mean * mean).max(0.);
+                (mean, variance.sqrt())
+            }
+            _ => (0., 0.),
+        }
+    }
+
+    fn most_failed(&self) -> Option<(RoleId, u32)> {
+        self.failed_roles
+            .iter()
+            .max_by_key(|(_role_id, failures)| **failures)
+            .map(|(role_id, failures)| (*role_id, *failures))
+    }
+}
+
+/// Runs the whole assignment many times with randomly qualified players.
+fn run_trials(
+    jobs: &mut Jobs,
+    player_count: u16,
+    chance: f64,
+    trials: u32,
+    method: Apportionment,
+) -> TrialStats {
+    let rng = &mut rand::thread_rng();
+    let mut stats = TrialStats {
+        trials,
+        failures: 0,
+        failed_roles: HashMap::new(),
+        sums: HashMap::new(),
+    };
+    for _ in 0..trials {
+        let mut users_roles: HashMap<UserId, HashSet<RoleId>> = (0..player_count)
+            .map(|i| {
+                let roles = jobs
+                    .keys()
+                    .filter(|_role_id| rng.gen_bool(chance))
+                    .map(|role_id| RoleId(*role_id))
+                    .collect();
+                (UserId::from(i as u64), roles)
+            })
+            .collect();
+        remove_irrelevant_qualifications(&mut users_roles, jobs);
+        match decide_pairings(jobs, &users_roles, method) {
+            Ok(assigned) => {
+                for (role_id, players) in assigned {
+                    let amount = players.len() as f64;
+                    let (sum, squares) = stats.sums.entry(role_id).or_insert((0., 0.));
+                    *sum += amount;
+                    *squares += amount * amount;
+                }
+            }
+            Err(PairingError::Unqualified(role_id)) => {
+                stats.failures += 1;
+                *stats.failed_roles.entry(role_id).or_insert(0) += 1;
+            }
+            Err(PairingError::NoSlotsLeft) => stats.failures += 1,
+        }
+    }
+    stats
+}
+
+/// Returns why jobs can't be simulated, if they can't.
+pub fn unsimulatable(jobs: &Jobs) -> Option<&'static str> {
+    if jobs.is_empty() {
+        return Some("No roles to simulate.");
+    }
+    match jobs.values().all(|job| job.points.is_empty()) {
+        true => Some("No stages to simulate."),
+        false => None,
+    }
+}
+
+/// Parses a single player count like `8` or a range like `4-12`.
+fn parse_player_counts(text: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (start.trim().parse().ok()?, end.trim().parse().ok()?),
+        None => {
+            let count = text.trim().parse().ok()?;
+            (count, count)
+        }
+    };
+    if start > end || (end - start) as usize >= MAX_RANGE_LEN {
+        return None;
+    }
+    Some(start..=end)
+}
+
+/// Role ids of all jobs in a stable order.
+fn sorted_role_ids(jobs: &Jobs) -> Vec<RoleId> {
+    let mut role_ids: Vec<RoleId> = jobs.keys().map(|role_id| RoleId(*role_id)).collect();
+    role_ids.sort();
+    role_ids
+}
+
+/// Formats rows of cells into a code block table.
+fn format_table(header: Vec<String>, rows: Vec<(String, Vec<String>)>) -> String {
+    let max_name_len = rows.iter().map(|(name, _cells)| name.chars().count()).max().unwrap_or(0);
+    let max_cell_len = header
+        .iter()
+        .chain(rows.iter().flat_map(|(_name, cells)| cells.iter()))
+        .map(|cell| cell.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut content = String::new();
+    content.write_str("```").ok();
+    // Header
+    content.write_fmt(format_args!("{:<width$} ", "", width=max_name_len)).ok();
+    for cell in header {
+        content.write_fmt(format_args!("{:>width$} ", cell, width=max_cell_len)).ok();
+    }
+    // Rows
+    content.write_str("\n").ok();
+    for (name, cells) in rows {
+        content.write_fmt(format_args!("{:<width$} ", name, width=max_name_len)).ok();
+        for cell in cells {
+            content.write_fmt(format_args!("{:>width$} ", cell, width=max_cell_len)).ok();
+        }
+        content.write_str("\n").ok();
+    }
+    content.write_str("```").ok();
+    content
+}
+
+/// Creates players qualified for every job.
+pub fn simulate_roles(jobs: &HashMap<u64, Job>, count: u16) -> HashMap<UserId, HashSet<RoleId>> {
     let mut users_roles = HashMap::new();
     for i in 0..count {
         users_roles.insert(
             UserId::from(i as u64),
-            jobs.iter().map(|(role_id, _job)| RoleId(*role_id)).collect(),
+            jobs.keys().map(|role_id| RoleId(*role_id)).collect(),
         );
     }
     users_roles
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::{job, runner_guild, FakeGuild};
+
+    /// Guild with a "Runner" job needing a quarter of the players and "Reader" the rest.
+    fn runner_and_reader() -> (FakeGuild, Jobs) {
+        let (guild, mut jobs, _runner) = runner_guild(&[(4, 1)]);
+        let reader = guild.add_role("Reader");
+        jobs.insert(reader.0, job(&[(4, 3)]));
+        (guild, jobs)
+    }
+
+    #[tokio::test]
+    async fn simulates_player_counts() {
+        let (guild, mut jobs) = runner_and_reader();
+
+        let content = simulate_counts(&guild, &mut jobs, 8..=8, Apportionment::default()).await.unwrap();
+        assert_eq!(content, "Simulation for 8 players:\n**Runner**: 2\n**Reader**: 6\n");
+
+        let content = simulate_counts(&guild, &mut jobs, 4..=5, Apportionment::default()).await.unwrap();
+        assert!(content.contains("Runner 1 1 \nReader 3 4 \n"));
+    }
+
+    #[tokio::test]
+    async fn simulates_trials() {
+        let (guild, jobs) = runner_and_reader();
+
+        let content = simulate_trials(&guild, jobs, 8..=8, 1., 5, Apportionment::default()).await.unwrap();
+
+        assert!(content.starts_with("Monte Carlo simulation with 100% qualification chance over 5 trials:\n"));
+        assert!(content.contains("Runner 2.0±0.0 \n"));
+        assert!(content.contains("Failed    0.0% \n"));
+    }
+
+    #[tokio::test]
+    async fn needs_stages_to_simulate() {
+        let (guild, _jobs, _runner) = runner_guild(&[]);
+
+        let reply = simulate_counts(&guild, &mut Jobs::new(), 4..=4, Apportionment::default()).await;
+        assert_eq!(reply.unwrap_err().to_string(), "No roles to simulate.");
+
+        let (_guild, mut jobs, _runner) = runner_guild(&[]);
+        let reply = simulate_counts(&guild, &mut jobs, 4..=4, Apportionment::default()).await;
+        assert_eq!(reply.unwrap_err().to_string(), "No stages to simulate.");
+    }
+
+    #[test]
+    fn checks_stages_of_every_role() {
+        let mut jobs = Jobs::new();
+        jobs.insert(1, job(&[]));
+        jobs.insert(2, job(&[]));
+        assert_eq!(unsimulatable(&jobs), Some("No stages to simulate."));
+
+        jobs.insert(3, job(&[(4, 1)]));
+        assert_eq!(unsimulatable(&jobs), None);
+    }
+}
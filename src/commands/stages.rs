@@ -1,4 +1,7 @@
-use crate::model::{load_jobs, save_jobs};
+use crate::{
+    error::BotError,
+    model::{load_jobs, save_jobs, Jobs},
+};
 use serenity::{
     framework::standard::{
         macros::command,
@@ -9,6 +12,12 @@ use serenity::{
 };
 use std::{collections::HashMap, fmt::Write};
 
+use super::{
+    checks::MANAGER_CHECK,
+    guild::{DiscordGuild, GuildAccess},
+    util::{role_by_name, role_name, Reply},
+};
+
 #[command]
 #[only_in(guilds)]
 #[sub_commands(add, remove, list)]
@@ -16,8 +25,7 @@ use std::{collections::HashMap, fmt::Write};
 async fn stages(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
     msg.channel_id
         .say(ctx, "Invalid subcommand.".to_owned())
-        .await
-        .ok();
+        .await?;
 
     Ok(())
 }
@@ -25,147 +33,127 @@ async fn stages(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
 #[command]
 #[aliases("+")]
 #[only_in(guilds)]
+#[checks(Manager)]
 #[description("Add a stage for specific player count. Specify the role name, followed by amount of players. Repeat for all non-zero roles. The amount of players this will apply to will be specified by the sum of all players.")]
 async fn add(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult{
     let argc = args.len();
     if (argc == 0) || (argc % 2 != 0) {
-        msg.channel_id
-            .say(ctx, "Invalid amount of arguments.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
     }
 
     args.trimmed().quoted();
+    let pairs: Vec<(String, Option<u16>)> = (0..(argc / 2))
+        .map(|_| {
+            let name = args.single::<String>().unwrap();
+            (name, args.single::<u16>().ok())
+        })
+        .collect();
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
     let mut jobs = load_jobs();
+    let content = add_stage(&guild, &mut jobs, pairs).await?;
+    save_jobs(jobs);
+
+    guild.say(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Adds a stage for the sum of amounts, roles without an amount need nobody.
+async fn add_stage(
+    guild: &impl GuildAccess,
+    jobs: &mut Jobs,
+    pairs: Vec<(String, Option<u16>)>,
+) -> Reply {
+    let roles = guild.roles().await?;
     let mut total: u16 = 0;
-    let mut pairs: HashMap<u64, u16> = HashMap::new();
-    let partial_guild = msg
-        .guild_id
-        .unwrap()
-        .to_partial_guild(&ctx.http)
-        .await
-        .unwrap();
-
-    for _ in 0..(argc / 2) {
-        let name = args.single::<String>().unwrap();
-        let valid_amount = args.single::<u16>().ok();
-        let valid_job = partial_guild.role_by_name(&name).and_then(|role| {
-            jobs.get(&role.id.0).and_then(|_job| Some(role.id))
-        });
-        match (valid_job, valid_amount) {
+    let mut amounts: HashMap<u64, u16> = HashMap::new();
+    for (name, amount) in pairs {
+        let valid_job = role_by_name(&roles, &name).filter(|role_id| jobs.contains_key(&role_id.0));
+        match (valid_job, amount) {
             (Some(role_id), Some(amount)) => {
                 total += amount;
-                pairs.insert(role_id.0, amount);
-            }
-            (None, _) => {
-                msg.channel_id
-                    .say(ctx, format!("Invalid role name '{}'.", name))
-                    .await
-                    .ok();
-                return Ok(());
-            }
-            (_, None) => {
-                msg.channel_id
-                    .say(ctx, format!("Invalid amount for role '{}'.", name))
-                    .await
-                    .ok();
-                return Ok(());
+                amounts.insert(role_id.0, amount);
             }
+            (None, _) => return Err(BotError::Rejected(format!("Invalid role name '{}'.", name))),
+            (_, None) => return Err(BotError::Rejected(format!("Invalid amount for role '{}'.", name))),
         }
     }
 
     for (role_id, job) in jobs.iter_mut() {
-        let amount = match pairs.get(role_id) {
+        let amount = match amounts.get(role_id) {
             Some(amount) => *amount,
             None => 0,
         };
         job.points.insert(total, amount);
     }
-
-    save_jobs(jobs);
-
-    msg.channel_id
-        .say(ctx, "Stage succesfully added.".to_owned())
-        .await
-        .ok();
-    Ok(())
+    Ok("Stage succesfully added.".to_owned())
 }
 
 #[command]
 #[aliases("-")]
 #[only_in(guilds)]
+#[checks(Manager)]
 #[description("Remove a stage for specific player count.")]
 async fn remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult{
     if args.len() != 1 {
-        msg.channel_id
-            .say(ctx, "Invalid amount of arguments.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
     }
 
     args.trimmed().quoted();
     let result = args.single::<u16>();
 
-    if let Err(_) = result {
-        msg.channel_id
-            .say(ctx, "Invalid player count.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+    if result.is_err() {
+        return Err(BotError::rejected("Invalid player count.").into());
     }
 
     let player_count = result.unwrap();
     let mut jobs = load_jobs();
-    jobs.iter_mut().for_each(|(_role_id, job)| {
-        job.points.remove(&player_count);
-    });
+    remove_stage(&mut jobs, player_count);
     save_jobs(jobs);
 
     msg.channel_id
         .say(ctx, "Stage succesfully removed.".to_owned())
-        .await
-        .ok();
+        .await?;
     Ok(())
 }
 
+fn remove_stage(jobs: &mut Jobs, player_count: u16) {
+    jobs.iter_mut().for_each(|(_role_id, job)| {
+        job.points.remove(&player_count);
+    });
+}
+
 #[command]
 #[aliases("l")]
 #[only_in(guilds)]
 #[description("List all stages.")]
 async fn list(ctx: &Context, msg: &Message, args: Args) -> CommandResult{
-    if args.len() != 0 {
-        msg.channel_id
-            .say(ctx, "Invalid amount of arguments".to_owned())
-            .await
-            .ok();
-        return Ok(());
+    if !args.is_empty() {
+        return Err(BotError::rejected("Invalid amount of arguments").into());
     }
 
-    let mut jobs = load_jobs();
+    let guild = DiscordGuild::new(ctx, msg.guild_id.ok_or(BotError::NotInGuild)?);
+    let content = list_stages(&guild, load_jobs()).await?;
+    guild.say(msg.channel_id, &content).await?;
+
+    Ok(())
+}
+
+/// Formats a table of every job's amount in every stage.
+async fn list_stages(guild: &impl GuildAccess, mut jobs: Jobs) -> Reply {
     jobs.iter_mut().for_each(|(_role_id, job)| {
         job.points.sort_keys();
     });
-    if jobs.len() == 0 {
-        msg.channel_id
-            .say(ctx, "No roles to list.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+    if jobs.is_empty() {
+        return Ok("No roles to list.".to_owned());
     }
 
     let some_job = jobs.iter().next().unwrap().1;
-    if some_job.points.len() == 0 {
-        msg.channel_id
-            .say(ctx, "No stages to list.".to_owned())
-            .await
-            .ok();
-        return Ok(());
+    if some_job.points.is_empty() {
+        return Ok("No stages to list.".to_owned());
     }
 
-    let roles = msg.guild_id.unwrap().roles(&ctx.http).await.unwrap();
-    let max_name_len = jobs.iter().map(|(role_id, _job)| {roles.get(&RoleId(*role_id)).unwrap().name.chars().count()}).max().unwrap();
+    let roles = guild.roles().await?;
+    let max_name_len = jobs.keys().map(|role_id| {role_name(&roles, RoleId(*role_id)).chars().count()}).max().unwrap();
     let max_stage_len = some_job.points.keys().max().unwrap().to_string().chars().count();
 
     let mut content = String::new();
@@ -178,7 +166,7 @@ async fn list(ctx: &Context, msg: &Message, args: Args) -> CommandResult{
     // Rows
     content.write_str("\n").ok();
     for (role_id, job) in jobs {
-        let name = &roles.get(&RoleId(role_id)).unwrap().name;
+        let name = role_name(&roles, RoleId(role_id));
         content.write_fmt(format_args!("{:<width$} ", name, width=max_name_len)).ok();
         for (_player_amount, role_amount) in job.points {
             content.write_fmt(format_args!("{:>width$} ", role_amount, width=max_stage_len)).ok();
@@ -186,11 +174,91 @@ async fn list(ctx: &Context, msg: &Message, args: Args) -> CommandResult{
         content.write_str("\n").ok();
     }
     content.write_str("```").ok();
+    Ok(content)
+}
 
-    msg.channel_id
-        .say(ctx, content)
-        .await
-        .ok();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::{job, FakeGuild};
 
-    Ok(())
+    /// Guild with "Runner" and "Reader" jobs without stages.
+    fn guild_with_jobs() -> (FakeGuild, Jobs, RoleId, RoleId) {
+        let guild = FakeGuild::default();
+        let runner = guild.add_role("Runner");
+        let reader = guild.add_role("Reader");
+        let mut jobs = Jobs::new();
+        jobs.insert(runner.0, job(&[]));
+        jobs.insert(reader.0, job(&[]));
+        (guild, jobs, runner, reader)
+    }
+
+    #[tokio::test]
+    async fn add_uses_sum_of_amounts() {
+        let (guild, mut jobs, runner, reader) = guild_with_jobs();
+
+        let pairs = vec![("Runner".to_owned(), Some(1)), ("Reader".to_owned(), Some(3))];
+        assert!(add_stage(&guild, &mut jobs, pairs).await.is_ok());
+
+        assert_eq!(jobs[&runner.0].points.get(&4), Some(&1));
+        assert_eq!(jobs[&reader.0].points.get(&4), Some(&3));
+    }
+
+    #[tokio::test]
+    async fn add_fills_missing_roles_with_zero() {
+        let (guild, mut jobs, _runner, reader) = guild_with_jobs();
+
+        let pairs = vec![("Runner".to_owned(), Some(2))];
+        assert!(add_stage(&guild, &mut jobs, pairs).await.is_ok());
+
+        assert_eq!(jobs[&reader.0].points.get(&2), Some(&0));
+    }
+
+    #[tokio::test]
+    async fn add_rejects_invalid_pairs() {
+        let (guild, mut jobs, runner, _reader) = guild_with_jobs();
+
+        let pairs = vec![("Spectator".to_owned(), Some(2))];
+        let reply = add_stage(&guild, &mut jobs, pairs).await;
+        assert_eq!(reply.unwrap_err().to_string(), "Invalid role name 'Spectator'.");
+
+        let pairs = vec![("Runner".to_owned(), None)];
+        let reply = add_stage(&guild, &mut jobs, pairs).await;
+        assert_eq!(reply.unwrap_err().to_string(), "Invalid amount for role 'Runner'.");
+
+        assert!(jobs[&runner.0].points.is_empty());
+    }
+
+    #[test]
+    fn remove_drops_stage_from_every_job() {
+        let (_guild, mut jobs, runner, reader) = guild_with_jobs();
+        jobs.values_mut().for_each(|job| {
+            job.points.insert(4, 2);
+        });
+
+        remove_stage(&mut jobs, 4);
+
+        assert!(jobs[&runner.0].points.is_empty());
+        assert!(jobs[&reader.0].points.is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_shows_table() {
+        let (guild, mut jobs, runner, reader) = guild_with_jobs();
+        jobs.get_mut(&runner.0).unwrap().points.insert(4, 1);
+        jobs.get_mut(&reader.0).unwrap().points.insert(4, 3);
+
+        let content = list_stages(&guild, jobs).await.unwrap();
+
+        assert!(content.contains("Runner 1 "));
+        assert!(content.contains("Reader 3 "));
+    }
+
+    #[tokio::test]
+    async fn list_without_stages() {
+        let (guild, jobs, _runner, _reader) = guild_with_jobs();
+
+        assert_eq!(list_stages(&guild, jobs).await.unwrap(), "No stages to list.");
+        assert_eq!(list_stages(&guild, Jobs::new()).await.unwrap(), "No roles to list.");
+    }
 }
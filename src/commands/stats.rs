@@ -0,0 +1,277 @@
+use crate::{
+    error::BotError,
+    model::{load_history, load_jobs, save_history, History, Jobs, RollRecord},
+};
+use indexmap::IndexMap;
+use serenity::{
+    framework::standard::{macros::command, Args, CommandResult},
+    model::prelude::*,
+    prelude::*,
+};
+use std::{collections::HashMap, fmt::Write};
+
+use super::{
+    guild::{DiscordGuild, GuildAccess},
+    util::{role_by_name, role_name, unix_now, Reply},
+};
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(job)]
+#[description("Show how often a player was assigned each role. Mention a player to see their statistics, otherwise shows the caller's.")]
+async fn stats(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() > 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let user_id = match args.single::<String>().ok() {
+        None => msg.author.id,
+        Some(arg) => match arg.parse::<UserId>() {
+            Ok(user_id) => user_id,
+            Err(_) => {
+                return Err(BotError::rejected(format!("Invalid player '{}'.", arg)).into());
+            }
+        },
+    };
+
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let history = guild_history(load_history(), guild_id);
+    let content = player_stats(&guild, &history, user_id).await?;
+
+    guild.say_quietly(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Describes how often a player was assigned each job and their streaks.
+async fn player_stats(guild: &impl GuildAccess, history: &[RollRecord], user_id: UserId) -> Reply {
+    let played = played_jobs(history, user_id.0);
+    if played.is_empty() {
+        return Ok(format!("<@{}> wasn't assigned any roles yet.", user_id));
+    }
+
+    let mut counts: IndexMap<u64, u32> = IndexMap::new();
+    for role_id in played.iter() {
+        *counts.entry(*role_id).or_insert(0) += 1;
+    }
+    counts.sort_by(|_role_id_a, a, _role_id_b, b| b.cmp(a));
+    let longest = longest_streaks(&played);
+    let current = current_streak(&played).unwrap();
+    let roles = guild.roles().await?;
+
+    let mut content = String::new();
+    content
+        .write_fmt(format_args!(
+            "Statistics of <@{}> over {} rolls:\n",
+            user_id,
+            played.len()
+        ))
+        .ok();
+    for (role_id, count) in counts {
+        content
+            .write_fmt(format_args!(
+                "**{}**: {} ({:.0}%), longest streak {}\n",
+                role_name(&roles, RoleId(role_id)),
+                count,
+                count as f64 * 100. / played.len() as f64,
+                longest[&role_id]
+            ))
+            .ok();
+    }
+    content
+        .write_fmt(format_args!(
+            "Currently on a streak of {} as {}.\n",
+            current.1,
+            role_name(&roles, RoleId(current.0))
+        ))
+        .ok();
+    Ok(content)
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Show how often each player was assigned a role, specified by it's name.")]
+async fn job(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.len() != 1 {
+        return Err(BotError::rejected("Invalid amount of arguments.").into());
+    }
+
+    args.trimmed().quoted();
+    let name = args.single::<String>().unwrap();
+    let guild_id = msg.guild_id.ok_or(BotError::NotInGuild)?;
+    let guild = DiscordGuild::new(ctx, guild_id);
+    let history = guild_history(load_history(), guild_id);
+    let content = job_stats(&guild, &load_jobs(), &history, &name).await?;
+
+    guild.say_quietly(msg.channel_id, &content).await?;
+    Ok(())
+}
+
+/// Describes how often each player was assigned a job, specified by its name.
+async fn job_stats(
+    guild: &impl GuildAccess,
+    jobs: &Jobs,
+    history: &[RollRecord],
+    name: &str,
+) -> Reply {
+    let role_id = match role_by_name(&guild.roles().await?, name) {
+        Some(role_id) if jobs.contains_key(&role_id.0) => role_id,
+        _ => return Err(BotError::rejected("Role doesn't exist.")),
+    };
+
+    let mut counts: IndexMap<u64, u32> = IndexMap::new();
+    for record in history.iter() {
+        for user_id in record.assigned.get(&role_id.0).into_iter().flatten() {
+            *counts.entry(*user_id).or_insert(0) += 1;
+        }
+    }
+    if counts.is_empty() {
+        return Ok(format!("Nobody was assigned '{}' yet.", name));
+    }
+    counts.sort_by(|_user_id_a, a, _user_id_b, b| b.cmp(a));
+    let total: u32 = counts.values().sum();
+
+    let mut content = String::new();
+    content
+        .write_fmt(format_args!(
+            "Statistics of '{}' over {} assignments:\n",
+            name, total
+        ))
+        .ok();
+    for (user_id, count) in counts {
+        let played = played_jobs(history, user_id);
+        let streak = longest_streaks(&played).get(&role_id.0).copied().unwrap_or(0);
+        content
+            .write_fmt(format_args!(
+                "- <@{}>: {} ({:.0}% of the role, {:.0}% of their rolls), longest streak {},\n",
+                user_id,
+                count,
+                count as f64 * 100. / total as f64,
+                count as f64 * 100. / played.len() as f64,
+                streak
+            ))
+            .ok();
+    }
+    Ok(content)
+}
+
+/// Appends a successful roll to the history.
+pub fn record_roll(guild_id: GuildId, assigned: &IndexMap<RoleId, Vec<UserId>>) {
+    let mut history = load_history();
+    history.push(RollRecord {
+        guild_id: guild_id.0,
+        rolled_at: unix_now(),
+        assigned: assigned
+            .iter()
+            .map(|(role_id, players)| (role_id.0, players.iter().map(|user_id| user_id.0).collect()))
+            .collect(),
+    });
+    save_history(history);
+}
+
+/// Keeps only rolls from a single guild.
+fn guild_history(history: History, guild_id: GuildId) -> History {
+    history
+        .into_iter()
+        .filter(|record| record.guild_id == guild_id.0)
+        .collect()
+}
+
+/// Returns the job a player was assigned in each roll they took part in.
+fn played_jobs(history: &[RollRecord], user_id: u64) -> Vec<u64> {
+    history
+        .iter()
+        .filter_map(|record| {
+            record
+                .assigned
+                .iter()
+                .find(|(_role_id, players)| players.contains(&user_id))
+                .map(|(role_id, _players)| *role_id)
+        })
+        .collect()
+}
+
+/// Longest run of consecutive rolls with the same job, for every job.
+fn longest_streaks(played: &[u64]) -> HashMap<u64, u32> {
+    let mut longest = HashMap::new();
+    let mut streak = 0;
+    for (i, role_id) in played.iter().enumerate() {
+        streak = match i > 0 && played[i - 1] == *role_id {
+            true => streak + 1,
+            false => 1,
+        };
+        let best = longest.entry(*role_id).or_insert(0);
+        *best = streak.max(*best);
+    }
+    longest
+}
+
+/// Job of the latest roll and how many rolls in a row it was assigned.
+fn current_streak(played: &[u64]) -> Option<(u64, u32)> {
+    let last = *played.last()?;
+    let streak = played.iter().rev().take_while(|role_id| **role_id == last).count();
+    Some((last, streak as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::{job, runner_guild};
+
+    fn record(assigned: &[(RoleId, &[UserId])]) -> RollRecord {
+        RollRecord {
+            guild_id: 0,
+            rolled_at: 0,
+            assigned: assigned
+                .iter()
+                .map(|(role_id, players)| (role_id.0, players.iter().map(|user_id| user_id.0).collect()))
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_jobs_and_streaks_of_a_player() {
+        let (guild, _jobs, runner) = runner_guild(&[(2, 1)]);
+        let reader = guild.add_role("Reader");
+        let player = guild.add_member("Player", &[runner, reader]);
+        let history = vec![
+            record(&[(runner, &[player])]),
+            record(&[(runner, &[player])]),
+            record(&[(reader, &[player])]),
+        ];
+
+        let content = player_stats(&guild, &history, player).await.unwrap();
+
+        assert!(content.starts_with(&format!("Statistics of <@{}> over 3 rolls:\n", player)));
+        assert!(content.contains("**Runner**: 2 (67%), longest streak 2\n"));
+        assert!(content.ends_with("Currently on a streak of 1 as Reader.\n"));
+        assert_eq!(
+            player_stats(&guild, &[], player).await.unwrap(),
+            format!("<@{}> wasn't assigned any roles yet.", player)
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_players_of_a_job() {
+        let (guild, mut jobs, runner) = runner_guild(&[(2, 1)]);
+        let reader = guild.add_role("Reader");
+        jobs.insert(reader.0, job(&[(2, 1)]));
+        let player = guild.add_member("Player", &[runner, reader]);
+        let history = vec![record(&[(runner, &[player])]), record(&[(reader, &[player])])];
+
+        let content = job_stats(&guild, &jobs, &history, "Runner").await.unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "Statistics of 'Runner' over 1 assignments:\n- <@{}>: 1 (100% of the role, 50% of their rolls), longest streak 1,\n",
+                player
+            )
+        );
+
+        let reply = job_stats(&guild, &jobs, &history, "Spectator").await;
+        assert_eq!(reply.unwrap_err().to_string(), "Role doesn't exist.");
+        let content = job_stats(&guild, &jobs, &[], "Reader").await.unwrap();
+        assert_eq!(content, "Nobody was assigned 'Reader' yet.");
+    }
+}
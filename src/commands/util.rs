@@ -1,53 +1,80 @@
-use crate::model::{Job, load_excluded};
-use serenity::{
-    model::prelude::*,
-    prelude::*,
+use crate::{
+    error::BotError,
+    model::{GuildQualifications, Job},
+};
+use serenity::model::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use std::collections::{HashMap, HashSet};
 
-/// Returns the voice channel id of the caller.
-pub async fn get_callers_vc(ctx: &Context, msg: &Message) -> Option<ChannelId> {
-    let guild = msg.guild(ctx).await.unwrap();
-    let result = guild.voice_states.get(&msg.author.id);
-    if let Some(voice_state) = result {
-        return voice_state.channel_id;
+use super::guild::GuildAccess;
+
+/// Reply to a command, the text to send or the error shown to the user instead.
+pub type Reply = Result<String, BotError>;
+
+/// Returns the name of a role or a placeholder if it no longer exists.
+pub fn role_name(roles: &HashMap<RoleId, String>, role_id: RoleId) -> String {
+    match roles.get(&role_id) {
+        Some(name) => name.clone(),
+        None => format!("Deleted role ({})", role_id),
     }
-    None
 }
 
-/// Returns all users in a voice channel.
-pub async fn get_members_in_vc(ctx: &Context, msg: &Message, voice_channel_id: ChannelId) -> Vec<Member> {
-    let channel = voice_channel_id
-        .to_channel(&ctx.http)
-        .await
-        .unwrap()
-        .guild()
-        .unwrap();
-    let guild_id = msg.guild_id.unwrap();
-    let members = channel.members(ctx)
+/// Returns the id of a role with the given name.
+pub fn role_by_name(roles: &HashMap<RoleId, String>, name: &str) -> Option<RoleId> {
+    roles
+        .iter()
+        .find(|(_role_id, role_name)| *role_name == name)
+        .map(|(role_id, _role_name)| *role_id)
+}
+
+/// Returns the voice channel id of the caller.
+pub async fn get_callers_vc(guild: &impl GuildAccess, user_id: UserId) -> Option<ChannelId> {
+    guild.voice_channel(user_id).await
+}
+
+/// Returns all users in a voice channel and their roles.
+pub async fn get_members_in_vc(
+    guild: &impl GuildAccess,
+    voice_channel_id: ChannelId,
+) -> HashMap<UserId, HashSet<RoleId>> {
+    let user_ids: Vec<u64> = guild
+        .voice_members(voice_channel_id)
         .await
-        .unwrap();
-    let mut new_members = Vec::new();
-    
-    for member in members {
-        let member = guild_id.member(&ctx.http, member.user.id).await.unwrap();
-        new_members.push(member);
-    }
-    new_members
+        .into_iter()
+        .map(|user_id| user_id.0)
+        .collect();
+    get_members(guild, &user_ids).await
 }
 
-/// Turns a vector of members into a map of users and their roles.
-pub fn get_users_roles(members: Vec<Member>) -> HashMap<UserId, HashSet<RoleId>> {
-    members.into_iter().map(|member| {
-        (member.user.id, member.roles.into_iter().collect())
-    }).collect()
+/// Returns roles of users with the given ids, skipping ones who left the guild.
+pub async fn get_members(
+    guild: &impl GuildAccess,
+    user_ids: &[u64],
+) -> HashMap<UserId, HashSet<RoleId>> {
+    let user_ids: Vec<UserId> = user_ids.iter().copied().map(UserId).collect();
+    guild.members_roles(&user_ids).await
+}
+
+/// Adds registered qualifications to the roles of users.
+pub fn merge_qualifications(
+    users_roles: &mut HashMap<UserId, HashSet<RoleId>>,
+    qualifications: &GuildQualifications,
+) {
+    for (user_id, roles) in users_roles.iter_mut() {
+        if let Some(role_ids) = qualifications.get(&user_id.0) {
+            roles.extend(role_ids.iter().copied().map(RoleId));
+        }
+    }
 }
 
 /// Remove players with exclude role.
 pub fn remove_excluded(
-    users_roles: &mut HashMap<UserId, HashSet<RoleId>>
+    users_roles: &mut HashMap<UserId, HashSet<RoleId>>,
+    excluded: Option<u64>,
 ) {
-    if let Some(excluded) = load_excluded() {
+    if let Some(excluded) = excluded {
         let role_id = RoleId(excluded);
         users_roles.retain(|_user_id, roles| {
             roles.get(&role_id).is_none()
@@ -55,15 +82,86 @@ pub fn remove_excluded(
     }
 }
 
-/// Removes qualifications that don't have jobs.
+/// Removes roles that don't qualify for any job.
 pub fn remove_irrelevant_qualifications(
     users: &mut HashMap<UserId, HashSet<RoleId>>,
     jobs: &HashMap<u64, Job>,
 ) {
-    for (_id, roles) in users.iter_mut() {
-        roles.retain(|role_id| jobs.get(&role_id.0).is_some());
-    }
-    users.retain(|_user_id, roles| {
-        roles.len() > 0
+    role_dispatch_core::remove_irrelevant_qualifications(users, |role_id| {
+        jobs.contains_key(&role_id.0) || jobs.values().any(|job| job.tiers.contains(&role_id.0))
     })
+}
+
+/// Returns the jobs a player with the given roles qualifies for and the highest tier of each,
+/// 0 for the job's own role and 1 onwards for its tiers.
+pub fn qualification_tiers(jobs: &HashMap<u64, Job>, roles: &HashSet<RoleId>) -> HashMap<RoleId, u8> {
+    jobs.iter()
+        .filter_map(|(role_id, job)| {
+            let tier = match roles.contains(&RoleId(*role_id)) {
+                true => Some(0),
+                false => job
+                    .tiers
+                    .iter()
+                    .position(|tier_role_id| roles.contains(&RoleId(*tier_role_id)))
+                    .map(|position| position as u8 + 1),
+            };
+            tier.map(|tier| (RoleId(*role_id), tier))
+        })
+        .collect()
+}
+
+/// Returns the current unix timestamp in seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses an `on` or `off` option.
+pub fn parse_switch(option: &str) -> Result<bool, BotError> {
+    match option {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(BotError::rejected("Invalid option, expected `on` or `off`.")),
+    }
+}
+
+/// Parses durations like `90s`, `15m`, `2h` or `1h30m`.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let scale = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        let value = number.parse::<u64>().ok()?;
+        total = total.checked_add(value.checked_mul(scale)?)?;
+        number.clear();
+    }
+    if !number.is_empty() || total == 0 {
+        return None;
+    }
+    Some(Duration::from_secs(total))
+}
+
+/// Formats durations the same way `parse_duration` reads them.
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    let mut text = String::new();
+    for (unit, scale) in [('d', 24 * 60 * 60), ('h', 60 * 60), ('m', 60), ('s', 1)] {
+        if seconds >= scale {
+            text.push_str(&format!("{}{}", seconds / scale, unit));
+            seconds %= scale;
+        }
+    }
+    text
 }
\ No newline at end of file
@@ -0,0 +1,130 @@
+//! Settings of a bot instance, read from a settings file and command line flags.
+//!
+//! `role_dispatch [--config <config.ron>] [--data-dir <dir>] [--prefix <prefix>] [--http-address <host:port>]`
+
+use serde::Deserialize;
+use std::{fs::File, path::PathBuf, sync::OnceLock};
+
+/// Settings file used when `--config` isn't given, it doesn't have to exist.
+const DEFAULT_CONFIG_PATH: &str = "config.ron";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Environment variable with the Discord bot token.
+    pub token_var: String,
+    /// Prefix of commands in guilds without their own.
+    pub prefix: String,
+    /// Separators of command arguments.
+    pub delimiters: Vec<String>,
+    /// Directory with the data files.
+    pub data_dir: PathBuf,
+    /// Address the local HTTP API listens on, if built with it.
+    pub http_address: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token_var: "DISCORD_TOKEN".to_owned(),
+            prefix: "!".to_owned(),
+            delimiters: vec![", ".to_owned(), ",".to_owned()],
+            data_dir: PathBuf::from("."),
+            http_address: "127.0.0.1:7878".to_owned(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings file and applies flags over it.
+    /// Returns the arguments which weren't flags of the settings.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<(Self, Vec<String>), String> {
+        let mut config_path = None;
+        let mut data_dir = None;
+        let mut prefix = None;
+        let mut http_address = None;
+        let mut rest = Vec::new();
+        while let Some(arg) = args.next() {
+            let value = match arg.as_str() {
+                "--config" => &mut config_path,
+                "--data-dir" => &mut data_dir,
+                "--prefix" => &mut prefix,
+                "--http-address" => &mut http_address,
+                _ => {
+                    rest.push(arg);
+                    continue;
+                }
+            };
+            match args.next() {
+                Some(next) => *value = Some(next),
+                None => return Err(format!("Expected a value after `{}`.", arg)),
+            }
+        }
+
+        let mut config = match config_path {
+            Some(path) => Self::read(&path)?,
+            None if PathBuf::from(DEFAULT_CONFIG_PATH).exists() => Self::read(DEFAULT_CONFIG_PATH)?,
+            None => Self::default(),
+        };
+        if let Some(data_dir) = data_dir {
+            config.data_dir = PathBuf::from(data_dir);
+        }
+        if let Some(prefix) = prefix {
+            config.prefix = prefix;
+        }
+        if let Some(http_address) = http_address {
+            config.http_address = http_address;
+        }
+        Ok((config, rest))
+    }
+
+    fn read(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|why| format!("Failed to open '{}': {}", path, why))?;
+        ron::de::from_reader(file).map_err(|why| format!("Failed to read '{}': {}", path, why))
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Sets the settings of this instance, only the first call has an effect.
+pub fn init(config: Config) {
+    CONFIG.set(config).ok();
+}
+
+/// Settings of this instance, defaults until they are set.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        let (config, rest) = Config::from_args(args(&["--prefix", "?", "--data-dir", "staging"])).unwrap();
+
+        assert_eq!(config.prefix, "?");
+        assert_eq!(config.data_dir, PathBuf::from("staging"));
+        assert_eq!(config.token_var, "DISCORD_TOKEN");
+        assert_eq!(config.http_address, "127.0.0.1:7878");
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn keeps_other_arguments() {
+        let (_config, rest) = Config::from_args(args(&["--data-dir", "staging", "roll", "players.ron"])).unwrap();
+
+        assert_eq!(rest, vec!["roll", "players.ron"]);
+    }
+
+    #[test]
+    fn rejects_missing_values() {
+        assert!(Config::from_args(args(&["--prefix"])).is_err());
+        assert!(Config::from_args(args(&["--config", "missing.ron"])).is_err());
+    }
+}
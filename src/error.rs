@@ -0,0 +1,64 @@
+//! Errors of the bot and how they are presented to users.
+
+use serenity::model::prelude::*;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum BotError {
+    /// The command was refused, the message explains why to the user.
+    Rejected(String),
+    /// Command can only be used in a server.
+    NotInGuild,
+    /// Caller has to be in a voice channel.
+    NotInVoiceChannel,
+    /// A role the bot relies on was deleted.
+    MissingRole(RoleId),
+    /// Request to Discord failed.
+    Discord(serenity::Error),
+}
+
+impl BotError {
+    pub fn rejected(message: impl Into<String>) -> Self {
+        Self::Rejected(message.into())
+    }
+
+    /// Message shown to the user who caused the error.
+    pub fn user_message(&self) -> String {
+        match self {
+            Self::Rejected(message) => message.clone(),
+            Self::NotInGuild => "This command can only be used in a server.".to_owned(),
+            Self::NotInVoiceChannel => "You need to be in a voice channel.".to_owned(),
+            Self::MissingRole(role_id) => format!(
+                "Role {} no longer exists, check the configuration with `roles list` and `settings`.",
+                role_id
+            ),
+            Self::Discord(_) => {
+                "Discord refused the request, check the bot's permissions and try again.".to_owned()
+            }
+        }
+    }
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Discord(why) => write!(f, "Discord error: {}", why),
+            _ => f.write_str(&self.user_message()),
+        }
+    }
+}
+
+impl std::error::Error for BotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Discord(why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<serenity::Error> for BotError {
+    fn from(why: serenity::Error) -> Self {
+        Self::Discord(why)
+    }
+}
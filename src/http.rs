@@ -0,0 +1,210 @@
+//! Local HTTP API for scripts and stream tooling.
+//!
+//! - `GET /jobs` lists jobs with their configuration,
+//! - `GET /stages` lists the amount of each job for every stage,
+//! - `GET /simulate?players=<count>[&method=<method>]` simulates a roll for a player count,
+//! - `POST /roll?guild=<id>&channel=<id>&voice=<id>` rolls for a voice channel
+//!   and announces the result in a text channel,
+//! - `/overlay` serves the stream overlay, see [`overlay`].
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use indexmap::IndexMap;
+use role_dispatch_core::Apportionment;
+use serde_json::{json, Value};
+use serenity::{model::prelude::*, prelude::*};
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr};
+
+pub mod overlay;
+
+use crate::{
+    commands::{
+        roll::{assign_roles, decide_pairings, describe_failure, RollTarget},
+        simulate::{simulate_roles, unsimulatable},
+    },
+    config,
+    model::load_jobs,
+};
+
+/// Serves the API on the configured address until the server fails.
+pub async fn serve(ctx: Context) {
+    let address = &config::get().http_address;
+    let address: SocketAddr = match address.parse() {
+        Ok(address) => address,
+        Err(why) => {
+            tracing::error!(%address, error = ?why, "invalid HTTP API address");
+            return;
+        }
+    };
+    if !address.ip().is_loopback() {
+        tracing::warn!(
+            %address,
+            "HTTP API is reachable from other machines, anyone who can reach it can trigger rolls"
+        );
+    }
+    let server = match Server::try_bind(&address) {
+        Ok(server) => server,
+        Err(why) => {
+            tracing::error!(%address, error = %why, "failed to bind HTTP API");
+            return;
+        }
+    };
+    let make_service = make_service_fn(move |_connection| {
+        let ctx = ctx.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle(ctx.clone(), request)))
+        }
+    });
+
+    tracing::info!(%address, "HTTP API listening");
+    if let Err(why) = server.serve(make_service).await {
+        tracing::error!(error = ?why, "HTTP API failed");
+    }
+}
+
+async fn handle(ctx: Context, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let query = parse_query(request.uri().query().unwrap_or(""));
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/jobs") => get_jobs(),
+        (&Method::GET, "/stages") => get_stages(),
+        (&Method::GET, "/simulate") => get_simulate(&query),
+        (&Method::POST, "/roll") => post_roll(&ctx, &query).await,
+        (&Method::GET, "/overlay") => overlay::page(),
+        (&Method::GET, "/overlay/roll") => overlay::latest(&ctx, guild_filter(&query)).await,
+        (&Method::GET, "/overlay/events") => overlay::events(&ctx, guild_filter(&query)).await,
+        _ => error(StatusCode::NOT_FOUND, "Not found."),
+    };
+    Ok(response)
+}
+
+fn get_jobs() -> Response<Body> {
+    let jobs: HashMap<String, Value> = load_jobs()
+        .into_iter()
+        .map(|(role_id, job)| {
+            (
+                role_id.to_string(),
+                json!({
+                    "points": job.points,
+                    "instructions": job.instructions,
+                    "session_role": job.session_role.map(|id| id.to_string()),
+                    "voice_channel": job.voice_channel.map(|id| id.to_string()),
+                    "priority": job.priority,
+                    "tiers": job.tiers.iter().map(|id| id.to_string()).collect::<Vec<_>>(),
+                }),
+            )
+        })
+        .collect();
+    respond(StatusCode::OK, json!(jobs))
+}
+
+fn get_stages() -> Response<Body> {
+    let jobs = load_jobs();
+    let mut player_counts: Vec<u16> = jobs
+        .values()
+        .flat_map(|job| job.points.keys().copied())
+        .collect();
+    player_counts.sort_unstable();
+    player_counts.dedup();
+    let stages: Vec<Value> = player_counts
+        .into_iter()
+        .map(|player_count| {
+            let roles: HashMap<String, u16> = jobs
+                .iter()
+                .map(|(role_id, job)| {
+                    let amount = job.points.get(&player_count).copied().unwrap_or(0);
+                    (role_id.to_string(), amount)
+                })
+                .collect();
+            json!({ "players": player_count, "roles": roles })
+        })
+        .collect();
+    respond(StatusCode::OK, json!(stages))
+}
+
+fn get_simulate(query: &HashMap<String, String>) -> Response<Body> {
+    let player_count = match query.get("players").and_then(|players| players.parse::<u16>().ok()) {
+        Some(player_count) => player_count,
+        None => return error(StatusCode::BAD_REQUEST, "Invalid player count."),
+    };
+    let method = match query.get("method").map(|method| method.parse::<Apportionment>()) {
+        Some(Ok(method)) => method,
+        Some(Err(())) => return error(StatusCode::BAD_REQUEST, "Unknown apportionment method."),
+        None => Apportionment::default(),
+    };
+    let mut jobs = load_jobs();
+    if let Some(reason) = unsimulatable(&jobs) {
+        return error(StatusCode::CONFLICT, reason);
+    }
+    let users_roles = simulate_roles(&jobs, player_count);
+    match decide_pairings(&mut jobs, &users_roles, method) {
+        Ok(assigned) => {
+            let roles: HashMap<String, usize> = assigned
+                .into_iter()
+                .map(|(role_id, players)| (role_id.to_string(), players.len()))
+                .collect();
+            respond(StatusCode::OK, json!({ "players": player_count, "roles": roles }))
+        }
+        Err(why) => error(StatusCode::CONFLICT, &describe_failure(why, |role_id| role_id.to_string())),
+    }
+}
+
+async fn post_roll(ctx: &Context, query: &HashMap<String, String>) -> Response<Body> {
+    let id = |name: &str| query.get(name).and_then(|id| id.parse::<u64>().ok());
+    let target = match (id("guild"), id("channel")) {
+        (Some(guild_id), Some(channel_id)) => RollTarget {
+            guild_id: GuildId(guild_id),
+            channel_id: ChannelId(channel_id),
+            voice_channel_id: id("voice").map(ChannelId),
+        },
+        _ => return error(StatusCode::BAD_REQUEST, "Expected `guild` and `channel` ids."),
+    };
+    match assign_roles(ctx, target).await {
+        Ok(Some(Ok(assigned))) => respond(StatusCode::OK, assignment_json(&assigned)),
+        Ok(Some(Err(why))) => {
+            error(StatusCode::CONFLICT, &describe_failure(why, |role_id| role_id.to_string()))
+        }
+        Ok(None) => error(StatusCode::CONFLICT, "No players to roll for."),
+        Err(why) => error(StatusCode::BAD_GATEWAY, &why.user_message()),
+    }
+}
+
+/// Players assigned to each job, ids are strings to survive JavaScript numbers.
+fn assignment_json(assigned: &IndexMap<RoleId, Vec<UserId>>) -> Value {
+    let roles: HashMap<String, Vec<String>> = assigned
+        .iter()
+        .map(|(role_id, players)| {
+            (
+                role_id.to_string(),
+                players.iter().map(|user_id| user_id.to_string()).collect(),
+            )
+        })
+        .collect();
+    json!({ "roles": roles })
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// Optional `guild` id narrowing results down to one guild.
+fn guild_filter(query: &HashMap<String, String>) -> Option<u64> {
+    query.get("guild").and_then(|id| id.parse::<u64>().ok())
+}
+
+fn respond(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    respond(status, json!({ "error": message }))
+}
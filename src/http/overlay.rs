@@ -0,0 +1,152 @@
+//! Stream overlay showing the most recent roll of each guild.
+//!
+//! - `GET /overlay?guild=<id>` serves a page usable as an OBS browser source,
+//! - `GET /overlay/roll?guild=<id>` returns the most recent roll as JSON,
+//! - `GET /overlay/events?guild=<id>` pushes every new roll as server-sent events.
+//!
+//! Without a guild the most recent roll of any guild is used.
+
+use hyper::{body::Bytes, Body, Response, StatusCode};
+use indexmap::IndexMap;
+use serde::Serialize;
+use serenity::{model::prelude::*, prelude::*};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::watch;
+
+use super::{error, respond};
+use crate::commands::util::{role_name, unix_now};
+
+const PAGE: &str = include_str!("overlay.html");
+
+/// Most recent roll of each guild, keyed by guild id.
+pub struct LatestRolls {
+    sender: watch::Sender<HashMap<u64, RollSnapshot>>,
+    // Kept so updates are stored even with no overlay open.
+    receiver: watch::Receiver<HashMap<u64, RollSnapshot>>,
+}
+
+impl Default for LatestRolls {
+    fn default() -> Self {
+        let (sender, receiver) = watch::channel(HashMap::new());
+        Self { sender, receiver }
+    }
+}
+
+impl TypeMapKey for LatestRolls {
+    type Value = Arc<LatestRolls>;
+}
+
+#[derive(Clone, Serialize)]
+pub struct RollSnapshot {
+    guild_id: String,
+    rolled_at: u64,
+    round: Option<usize>,
+    jobs: Vec<JobSnapshot>,
+}
+
+#[derive(Clone, Serialize)]
+struct JobSnapshot {
+    name: String,
+    players: Vec<String>,
+}
+
+/// Shares a roll with every open overlay.
+pub async fn publish_roll(
+    ctx: &Context,
+    guild_id: GuildId,
+    round: Option<usize>,
+    assigned: &IndexMap<RoleId, Vec<UserId>>,
+    roles: &HashMap<RoleId, String>,
+) {
+    let latest_rolls = match ctx.data.read().await.get::<LatestRolls>() {
+        Some(latest_rolls) => latest_rolls.clone(),
+        None => return,
+    };
+    let mut jobs = Vec::new();
+    for (role_id, players) in assigned {
+        let mut names = Vec::new();
+        for user_id in players {
+            let name = match guild_id.member(ctx, user_id).await {
+                Ok(member) => member.display_name().into_owned(),
+                Err(_) => user_id.to_string(),
+            };
+            names.push(name);
+        }
+        jobs.push(JobSnapshot {
+            name: role_name(roles, *role_id),
+            players: names,
+        });
+    }
+    jobs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut rolls = latest_rolls.receiver.borrow().clone();
+    rolls.insert(
+        guild_id.0,
+        RollSnapshot {
+            guild_id: guild_id.to_string(),
+            rolled_at: unix_now(),
+            round,
+            jobs,
+        },
+    );
+    latest_rolls.sender.send(rolls).ok();
+}
+
+pub fn page() -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(PAGE))
+        .unwrap()
+}
+
+pub async fn latest(ctx: &Context, guild_id: Option<u64>) -> Response<Body> {
+    let latest_rolls = match ctx.data.read().await.get::<LatestRolls>() {
+        Some(latest_rolls) => latest_rolls.clone(),
+        None => return error(StatusCode::SERVICE_UNAVAILABLE, "Overlay is unavailable."),
+    };
+    let roll = select(&latest_rolls.receiver.borrow(), guild_id);
+    match roll {
+        Some(roll) => respond(StatusCode::OK, serde_json::json!(roll)),
+        None => error(StatusCode::NOT_FOUND, "No rolls yet."),
+    }
+}
+
+pub async fn events(ctx: &Context, guild_id: Option<u64>) -> Response<Body> {
+    let mut receiver = match ctx.data.read().await.get::<LatestRolls>() {
+        Some(latest_rolls) => latest_rolls.receiver.clone(),
+        None => return error(StatusCode::SERVICE_UNAVAILABLE, "Overlay is unavailable."),
+    };
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut last_sent = None;
+        loop {
+            let roll = select(&receiver.borrow(), guild_id);
+            if let Some(roll) = roll {
+                let key = (roll.guild_id.clone(), roll.rolled_at, roll.round);
+                if last_sent.as_ref() != Some(&key) {
+                    let event = format!("data: {}\n\n", serde_json::json!(roll));
+                    if sender.send_data(Bytes::from(event)).await.is_err() {
+                        break;
+                    }
+                    last_sent = Some(key);
+                }
+            }
+            if receiver.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+    Response::builder()
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+/// Picks the roll of a guild or the most recent one.
+fn select(rolls: &HashMap<u64, RollSnapshot>, guild_id: Option<u64>) -> Option<RollSnapshot> {
+    match guild_id {
+        Some(guild_id) => rolls.get(&guild_id).cloned(),
+        None => rolls.values().max_by_key(|roll| roll.rolled_at).cloned(),
+    }
+}
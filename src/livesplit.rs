@@ -0,0 +1,131 @@
+//! Client for the LiveSplit Server component.
+//!
+//! The server accepts newline terminated text commands over TCP
+//! and answers queries with a single line.
+
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+/// Connection to a LiveSplit Server.
+pub struct LiveSplitClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl LiveSplitClient {
+    pub async fn connect(address: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(address).await?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    /// Sends a command and waits for the answer.
+    async fn query(&mut self, command: &str) -> io::Result<String> {
+        self.writer.write_all(command.as_bytes()).await?;
+        self.writer.write_all(b"\r\n").await?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end().to_owned())
+    }
+
+    /// Index of the current split, `-1` when the timer isn't running.
+    pub async fn split_index(&mut self) -> io::Result<i32> {
+        let answer = self.query("getsplitindex").await?;
+        answer
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, answer))
+    }
+
+    /// Name of the last completed split.
+    pub async fn previous_split_name(&mut self) -> io::Result<String> {
+        self.query("getprevioussplitname").await
+    }
+}
+
+/// Change of the timer worth reacting to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TimerEvent {
+    /// A split was completed.
+    Split,
+    /// The run was reset.
+    Reset,
+}
+
+/// Turns polled split indices into timer events.
+#[derive(Debug)]
+pub struct SplitWatcher {
+    last_index: i32,
+}
+
+impl Default for SplitWatcher {
+    fn default() -> Self {
+        Self { last_index: -1 }
+    }
+}
+
+impl SplitWatcher {
+    pub fn update(&mut self, index: i32) -> Option<TimerEvent> {
+        let last_index = std::mem::replace(&mut self.last_index, index);
+        match (last_index, index) {
+            (last, -1) if last >= 0 => Some(TimerEvent::Reset),
+            (last, index) if last >= 0 && index > last => Some(TimerEvent::Split),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::livesplit_server;
+
+    #[tokio::test]
+    async fn queries_the_server() {
+        let address = livesplit_server(&[
+            ("getsplitindex", "-1"),
+            ("getsplitindex", "2"),
+            ("getprevioussplitname", "Forest"),
+        ])
+        .await;
+        let mut client = LiveSplitClient::connect(&address).await.unwrap();
+
+        assert_eq!(client.split_index().await.unwrap(), -1);
+        assert_eq!(client.split_index().await.unwrap(), 2);
+        assert_eq!(client.previous_split_name().await.unwrap(), "Forest");
+    }
+
+    #[tokio::test]
+    async fn reports_invalid_answers_and_lost_connections() {
+        let address = livesplit_server(&[("getsplitindex", "running")]).await;
+        let mut client = LiveSplitClient::connect(&address).await.unwrap();
+
+        let why = client.split_index().await.unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+
+        let why = client.split_index().await.unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn turns_split_indices_into_events() {
+        let mut watcher = SplitWatcher::default();
+
+        assert_eq!(watcher.update(-1), None);
+        assert_eq!(watcher.update(0), None);
+        assert_eq!(watcher.update(0), None);
+        assert_eq!(watcher.update(1), Some(TimerEvent::Split));
+        assert_eq!(watcher.update(3), Some(TimerEvent::Split));
+        assert_eq!(watcher.update(-1), Some(TimerEvent::Reset));
+        assert_eq!(watcher.update(-1), None);
+    }
+}
@@ -0,0 +1,182 @@
+//! Logging setup and per command spans.
+
+use serenity::{
+    async_trait,
+    framework::{standard::CommandResult, Framework},
+    model::prelude::*,
+    prelude::*,
+};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::{field, info_span, Instrument, Span};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::error::BotError;
+
+/// Environment variable with the log filter, like `info` or `role_dispatch=debug`.
+const FILTER_VAR: &str = "RUST_LOG";
+/// Environment variable with a file that receives logs as JSON lines.
+const FILE_VAR: &str = "LOG_FILE";
+const DEFAULT_FILTER: &str = "warn,role_dispatch=info";
+
+/// Logs to the terminal and, if configured, as JSON to a file.
+pub fn init() {
+    let filter = EnvFilter::try_from_env(FILTER_VAR).unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let file = env::var(FILE_VAR).ok().and_then(|path| {
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(SharedFile(Arc::new(file))),
+            Err(why) => {
+                eprintln!("Failed to open log file '{}': {}", path, why);
+                None
+            }
+        }
+    });
+    let json = file.map(|file| {
+        fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(move || file.clone())
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(json)
+        .init();
+}
+
+/// Log file shared between writes.
+#[derive(Clone)]
+struct SharedFile(Arc<File>);
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self.0).flush()
+    }
+}
+
+/// Spans of messages which are still being dispatched.
+pub struct CommandSpans;
+
+impl TypeMapKey for CommandSpans {
+    type Value = Arc<Mutex<HashMap<MessageId, (Span, Instant)>>>;
+}
+
+/// Framework which dispatches every message, and the command it invokes, inside a span.
+pub struct Instrumented<F>(pub F);
+
+#[async_trait]
+impl<F: Framework> Framework for Instrumented<F> {
+    async fn dispatch(&self, ctx: Context, msg: Message) {
+        let span = command_span(msg.guild_id, msg.channel_id, msg.author.id);
+        let message_id = msg.id;
+        let spans = ctx.data.read().await.get::<CommandSpans>().cloned();
+        if let Some(spans) = &spans {
+            spans.lock().unwrap().insert(message_id, (span.clone(), Instant::now()));
+        }
+
+        self.0.dispatch(ctx, msg).instrument(span).await;
+
+        if let Some(spans) = spans {
+            spans.lock().unwrap().remove(&message_id);
+        }
+    }
+}
+
+/// Span of a message, the command name is recorded once it's known.
+fn command_span(guild_id: Option<GuildId>, channel_id: ChannelId, user_id: UserId) -> Span {
+    info_span!(
+        "command",
+        name = field::Empty,
+        guild = guild_id.map(|guild_id| guild_id.0),
+        channel = channel_id.0,
+        user = user_id.0,
+    )
+}
+
+/// Names the span of a message invoking a command and starts timing the command.
+pub async fn command_started(ctx: &Context, msg: &Message, command_name: &str) {
+    let spans = ctx.data.read().await.get::<CommandSpans>().cloned();
+    if let Some(spans) = spans {
+        if let Some((span, started_at)) = spans.lock().unwrap().get_mut(&msg.id) {
+            span.record("name", &command_name);
+            *started_at = Instant::now();
+        }
+    }
+}
+
+/// Logs how a command went and how long it took.
+pub async fn command_finished(ctx: &Context, msg: &Message, result: &CommandResult) {
+    let spans = ctx.data.read().await.get::<CommandSpans>().cloned();
+    let started = spans.and_then(|spans| spans.lock().unwrap().remove(&msg.id));
+    let (span, started_at) = match started {
+        Some(started) => started,
+        None => return,
+    };
+    let duration_ms = started_at.elapsed().as_millis() as u64;
+
+    let _entered = span.enter();
+    match result {
+        Ok(()) => tracing::info!(duration_ms, "command finished"),
+        Err(why) => match why.downcast_ref::<BotError>() {
+            Some(BotError::Rejected(reason)) => {
+                tracing::info!(duration_ms, reason = %reason, "command rejected")
+            }
+            _ => tracing::error!(duration_ms, error = %why, "command failed"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Log output collected in memory.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_inside_commands_carry_the_span_fields() {
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let span = command_span(Some(GuildId(1)), ChannelId(2), UserId(3));
+        span.record("name", &"roll");
+        async {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            tracing::info!("inside the command");
+        }
+        .instrument(span)
+        .await;
+
+        let logged = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(logged.contains("command{guild=1 channel=2 user=3 name=\"roll\"}"), "{}", logged);
+        assert!(logged.contains("inside the command"));
+    }
+}
@@ -1,12 +1,21 @@
+mod config;
 mod model;
+mod error;
+mod logging;
 mod commands;
+mod livesplit;
+mod cli;
+mod shutdown;
+#[cfg(feature = "http-api")]
+mod http;
 
 use serenity::{
     async_trait,
+    client::bridge::gateway::GatewayIntents,
     framework::standard::{
         help_commands,
-        macros::{group, help},
-        Args, CommandGroup, CommandResult, HelpOptions, StandardFramework,
+        macros::{group, help, hook},
+        Args, CommandGroup, CommandResult, DispatchError, HelpOptions, Reason, StandardFramework,
     },
     http::Http,
     model::prelude::*,
@@ -15,6 +24,11 @@ use serenity::{
 use std::{
     collections::HashSet,
     env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 use commands::{
@@ -23,10 +37,16 @@ use commands::{
     roll::*,
     exclude::*,
     simulate::*,
+    settings::*,
+    session::*,
+    stats::*,
+    schedule::ScheduledRolls,
+    livesplit::{LiveSplitConnections, LIVESPLIT_COMMAND},
+    qualify::*,
 };
 
 #[group("Speedrunning")]
-#[commands(roles, roll, stages, exclude, simulate)]
+#[commands(roles, roll, preview, stages, exclude, excluded, simulate, settings, session, regroup, stats, livesplit, qualify, unqualify, qualified)]
 struct Speedrunning;
 
 #[help]
@@ -38,16 +58,94 @@ async fn my_help(
     groups: &[&'static CommandGroup],
     owners: HashSet<UserId>,
 ) -> CommandResult {
-    let _ = help_commands::with_embeds(context, msg, args, &help_options, groups, owners).await;
+    let _ = help_commands::with_embeds(context, msg, args, help_options, groups, owners).await;
     Ok(())
 }
 
-struct Handler;
+/// Prefix of the guild the message was sent in, or the default one.
+#[hook]
+async fn command_prefix(ctx: &Context, msg: &Message) -> Option<String> {
+    let guild_prefix = match (msg.guild_id, ctx.data.read().await.get::<GuildPrefixes>()) {
+        (Some(guild_id), Some(prefixes)) => prefixes.read().await.get(&guild_id).cloned(),
+        _ => None,
+    };
+    Some(guild_prefix.unwrap_or_else(|| config::get().prefix.clone()))
+}
+
+/// Names the command's span, unless the bot is shutting down.
+#[hook]
+async fn before(ctx: &Context, msg: &Message, command_name: &str) -> bool {
+    if !shutdown::get(ctx).await.command_started() {
+        msg.channel_id.say(ctx, "Restarting, try again in a moment.").await.ok();
+        return false;
+    }
+    logging::command_started(ctx, msg, command_name).await;
+    true
+}
+
+/// Tells the caller why they can't use a command.
+#[hook]
+async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError) {
+    tracing::debug!(channel = msg.channel_id.0, user = msg.author.id.0, ?error, "command not dispatched");
+    if let DispatchError::CheckFailed(_, Reason::User(reason)) = error {
+        msg.channel_id.say(ctx, reason).await.ok();
+    }
+}
+
+/// Reports failed commands to the caller and logs how the command went.
+#[hook]
+async fn after(ctx: &Context, msg: &Message, _command_name: &str, result: CommandResult) {
+    logging::command_finished(ctx, msg, &result).await;
+    shutdown::get(ctx).await.command_finished();
+    if let Err(why) = result {
+        let content = match why.downcast_ref::<error::BotError>() {
+            Some(error) => error.user_message(),
+            None => "Something went wrong.".to_owned(),
+        };
+        msg.channel_id.say(ctx, content).await.ok();
+    }
+}
+
+/// How often expired exclusions are checked.
+const EXCLUSION_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct Handler {
+    background_started: AtomicBool,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
-    async fn ready(&self, _: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        tracing::info!(user = %ready.user.name, "connected");
+
+        // Ready is dispatched again on reconnects, only start background tasks once.
+        if !self.background_started.swap(true, Ordering::SeqCst) {
+            #[cfg(feature = "http-api")]
+            tokio::spawn(http::serve(ctx.clone()));
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(EXCLUSION_SWEEP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    release_expired_exclusions(&ctx).await;
+                }
+            });
+        }
+    }
+
+    async fn guild_role_delete(
+        &self,
+        _: Context,
+        guild_id: GuildId,
+        removed_role_id: RoleId,
+        _removed_role_data_if_available: Option<Role>,
+    ) {
+        if model::prune_job(removed_role_id.0) {
+            tracing::info!(role = removed_role_id.0, "pruned jobs for deleted role");
+        }
+        if model::prune_qualifications(guild_id.0, removed_role_id.0).await {
+            tracing::info!(role = removed_role_id.0, "pruned qualifications for deleted role");
+        }
     }
 }
 
@@ -55,8 +153,26 @@ impl EventHandler for Handler {
 async fn main() {
     dotenv::dotenv().ok();
 
+    let args = match config::Config::from_args(env::args().skip(1)) {
+        Ok((config, args)) => {
+            config::init(config);
+            args
+        }
+        Err(why) => {
+            eprintln!("{}", why);
+            std::process::exit(2);
+        }
+    };
+    if let Some(code) = cli::run(args.into_iter()) {
+        std::process::exit(code);
+    }
+
+    logging::init();
+
     // Configure the client with your Discord bot token in the environment.
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    let config = config::get();
+    let token = env::var(&config.token_var)
+        .unwrap_or_else(|_| panic!("Expected a token in the environment variable `{}`", config.token_var));
 
     let http = Http::new_with_token(&token);
 
@@ -68,21 +184,68 @@ async fn main() {
 
     let framework = StandardFramework::new()
         .configure(|c| {
+            // Prefixes come from the guild configuration, falling back to the instance's.
             c.with_whitespace(true)
                 .on_mention(Some(bot_id))
-                .prefix("!")
-                .delimiters(vec![", ", ","])
+                .prefix("")
+                .dynamic_prefix(command_prefix)
+                .delimiters(config.delimiters.iter().map(String::as_str))
         })
+        .before(before)
+        .on_dispatch_error(dispatch_error)
+        .after(after)
         .help(&MY_HELP)
         .group(&SPEEDRUNNING_GROUP);
 
+    // Voice states and members come from the gateway cache, listing members needs the privileged members intent.
     let mut client = Client::builder(&token)
-        .event_handler(Handler)
-        .framework(framework)
+        .intents(GatewayIntents::non_privileged() | GatewayIntents::GUILD_MEMBERS)
+        .event_handler(Handler::default())
+        .framework(logging::Instrumented(framework))
         .await
         .expect("Err creating client");
 
+    client
+        .data
+        .write()
+        .await
+        .insert::<ScheduledRolls>(Arc::default());
+    client
+        .data
+        .write()
+        .await
+        .insert::<LiveSplitConnections>(Arc::default());
+    client
+        .data
+        .write()
+        .await
+        .insert::<logging::CommandSpans>(Arc::default());
+    client
+        .data
+        .write()
+        .await
+        .insert::<GuildPrefixes>(Arc::new(RwLock::new(load_guild_prefixes())));
+    let shutdown = Arc::new(shutdown::Shutdown::default());
+    client
+        .data
+        .write()
+        .await
+        .insert::<shutdown::Shutdown>(shutdown.clone());
+    tokio::spawn(shutdown::on_signal(
+        shutdown,
+        client.data.clone(),
+        client.cache_and_http.clone(),
+        client.shard_manager.clone(),
+    ));
+    #[cfg(feature = "http-api")]
+    client
+        .data
+        .write()
+        .await
+        .insert::<http::overlay::LatestRolls>(Arc::default());
+
     if let Err(why) = client.start().await {
-        println!("Client error: {:?}", why);
+        tracing::error!(error = ?why, "client failed");
     }
+    tracing::info!("stopped");
 }
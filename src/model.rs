@@ -1,71 +1,116 @@
 use std::{
-    collections::HashMap,
-    fs::{File, OpenOptions},
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
 };
 
 use indexmap::IndexMap;
+use role_dispatch_core::{Apportionment, Stages};
 use ron::ser::PrettyConfig;
 use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Job {
-    pub points: IndexMap<u16, u16>,
+use crate::config;
+
+/// Path of a data file inside the configured data directory.
+pub fn data_path(file_name: &str) -> PathBuf {
+    config::get().data_dir.join(file_name)
 }
 
-impl Job {
-    pub fn interpolate(&mut self, player_count: u16) -> f64 {
-        self.points.sort_keys();
-        if let Some(amount) = self.points.get(&player_count) {
-            return *amount as f64
-        }
-        // No existing value, interpolate
-        let mut plausible_bottom: Option<(u16, u16)> = None;
-        let mut bottom: Option<(u16, u16)> = None;
-        let mut top: Option<(u16, u16)> = None;
-        for (point_player_count, point_amount) in self.points.iter() {
-            if player_count < *point_player_count {
-                plausible_bottom = Some((*point_player_count, *point_amount));
-            } else {
-                bottom = plausible_bottom;
-                top = Some((*point_player_count, *point_amount));
-                break
-            }
-        }
+/// Serializes saves, which would otherwise share the temporary file,
+/// and is held while a guild's entry is written back into a file shared by all guilds.
+static SAVING: Mutex<()> = Mutex::new(());
 
-        match (bottom, top) {
-            (Some((bottom_player_count, bottom_amount)), Some((top_player_count, top_amount))) => {
-                let player_diff = top_player_count as f64 - bottom_player_count as f64;
-                let amount_diff = top_amount as f64 - bottom_amount as f64;
-                let scale = amount_diff / player_diff;
-                bottom_amount as f64 + (player_count - bottom_player_count) as f64 * scale
-            },
-            (Some((bottom_player_count, bottom_amount)), None) => {
-                let player_diff = bottom_player_count as f64;
-                let amount_diff = bottom_amount as f64;
-                let scale = amount_diff / player_diff;
-                bottom_amount as f64 + (player_count - bottom_player_count) as f64 * scale
-            },
-            (None, Some((top_player_count, top_amount))) => {
-                let player_diff = top_player_count as f64;
-                let amount_diff = top_amount as f64;
-                let scale = amount_diff / player_diff;
-                player_count as f64 * scale
-            },
-            (None, None) => {
-                0.
-            },
-        }
+type GuildLocks = BTreeMap<(&'static str, u64), Arc<AsyncMutex<()>>>;
+
+/// Locks of guilds' entries in data files, created on first use.
+static GUILD_LOCKS: Mutex<GuildLocks> = Mutex::new(BTreeMap::new());
+
+/// Keeps other tasks from changing a guild's entry in a data file until dropped.
+pub type GuildLock = OwnedMutexGuard<()>;
+
+/// Waits until no other task changes the guild's entry in a data file.
+/// Held from loading the entry until it's saved back, so Discord requests in between
+/// can't let another task's change be overwritten.
+async fn lock_guild(file_name: &'static str, guild_id: u64) -> GuildLock {
+    let lock = GUILD_LOCKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry((file_name, guild_id))
+        .or_default()
+        .clone();
+    lock.lock_owned().await
+}
+
+/// Saves a data file, writing it next to the old one first
+/// so an interrupted write never leaves a truncated file behind.
+fn save_ron(file_name: &str, value: &impl Serialize) {
+    let _saving = SAVING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    write_ron(file_name, value);
+}
+
+/// Loads a data file, changes it and saves it back without another save landing in between.
+fn update_ron<T: Serialize, R>(
+    file_name: &str,
+    load: impl FnOnce() -> T,
+    update: impl FnOnce(&mut T) -> R,
+) -> R {
+    let _saving = SAVING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut value = load();
+    let result = update(&mut value);
+    write_ron(file_name, &value);
+    result
+}
+
+fn write_ron(file_name: &str, value: &impl Serialize) {
+    let path = data_path(file_name);
+    let temporary_path = path.with_extension("ron.tmp");
+    let written = File::create(&temporary_path).and_then(|mut file| {
+        let content = ron::ser::to_string_pretty(value, PrettyConfig::default())
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(why) = written.and_then(|()| fs::rename(&temporary_path, &path)) {
+        tracing::error!(path = %path.display(), error = %why, "failed to save data file");
     }
 }
 
-/// Path to the job configuration file.
-const JOBS_PATH: &'static str = r"jobs.ron";
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Job {
+    pub points: Stages,
+    /// Notes sent to players assigned to this job.
+    #[serde(default)]
+    pub instructions: Option<String>,
+    /// Role granted to players assigned to this job until the next roll.
+    #[serde(default)]
+    pub session_role: Option<u64>,
+    /// Voice channel players assigned to this job are moved to.
+    #[serde(default)]
+    pub voice_channel: Option<u64>,
+    /// Weight of the job when the weighted priority apportionment gives out remaining slots.
+    #[serde(default = "default_priority")]
+    pub priority: u16,
+    /// Roles also qualifying for this job, from the highest tier to the lowest.
+    /// Players with the job's own role are above all of them.
+    #[serde(default)]
+    pub tiers: Vec<u64>,
+}
+
+fn default_priority() -> u16 {
+    1
+}
+
+/// Name of the job configuration file.
+pub const JOBS_FILE: &str = "jobs.ron";
 
 pub type Jobs = HashMap<u64, Job>;
 
 /// Loads jobs from file.
 pub fn load_jobs() -> Jobs {
-    if let Ok(jobs_file) = File::open(JOBS_PATH) {
+    if let Ok(jobs_file) = File::open(data_path(JOBS_FILE)) {
         if let Ok(jobs) = ron::de::from_reader(jobs_file) {
             return jobs;
         }
@@ -73,40 +118,333 @@ pub fn load_jobs() -> Jobs {
     HashMap::new()
 }
 
+/// Removes the job tied to a role and unlinks it as a session role and tier,
+/// returns whether anything changed.
+pub fn prune_job(role_id: u64) -> bool {
+    let mut jobs = load_jobs();
+    let changed = prune_role(&mut jobs, role_id);
+    if changed {
+        save_jobs(jobs);
+    }
+    changed
+}
+
+fn prune_role(jobs: &mut Jobs, role_id: u64) -> bool {
+    let mut changed = jobs.remove(&role_id).is_some();
+    for job in jobs.values_mut() {
+        if job.session_role == Some(role_id) {
+            job.session_role = None;
+            changed = true;
+        }
+        let tiers = job.tiers.len();
+        job.tiers.retain(|tier| *tier != role_id);
+        changed |= job.tiers.len() != tiers;
+    }
+    changed
+}
+
 /// Save jobs to file.
 pub fn save_jobs(jobs: Jobs) {
-    if let Ok(jobs_file) = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(JOBS_PATH)
-    {
-        let config = PrettyConfig::default();
-        ron::ser::to_writer_pretty(jobs_file, &jobs, config).ok();
+    save_ron(JOBS_FILE, &jobs);
+}
+
+/// Exclusion of a single player from role distribution.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Exclusion {
+    /// Unix timestamp after which the player is included again.
+    pub until: Option<u64>,
+    /// Whether the player is included again when the session ends.
+    #[serde(default)]
+    pub until_session_end: bool,
+}
+
+const EXCLUSIONS_FILE: &str = "exclusions.ron";
+
+pub type GuildExclusions = HashMap<u64, Exclusion>;
+
+pub type Exclusions = HashMap<u64, GuildExclusions>;
+
+/// Loads exclusions from file.
+pub fn load_exclusions() -> Exclusions {
+    if let Ok(exclusions_file) = File::open(data_path(EXCLUSIONS_FILE)) {
+        if let Ok(exclusions) = ron::de::from_reader(exclusions_file) {
+            return exclusions;
+        }
+    }
+    HashMap::new()
+}
+
+/// Waits until no other task changes the exclusions of a guild.
+pub async fn lock_guild_exclusions(guild_id: u64) -> GuildLock {
+    lock_guild(EXCLUSIONS_FILE, guild_id).await
+}
+
+/// Loads exclusions of a single guild.
+pub fn load_guild_exclusions(guild_id: u64) -> GuildExclusions {
+    load_exclusions().remove(&guild_id).unwrap_or_default()
+}
+
+/// Save exclusions of a single guild, forgetting it if it has none left.
+pub fn save_guild_exclusions(guild_id: u64, guild_exclusions: GuildExclusions) {
+    update_ron(EXCLUSIONS_FILE, load_exclusions, |exclusions| {
+        match guild_exclusions.is_empty() {
+            true => exclusions.remove(&guild_id),
+            false => exclusions.insert(guild_id, guild_exclusions),
+        };
+    });
+}
+
+/// Per guild configuration of the bot.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct GuildConfig {
+    /// Role allowed to change the configuration.
+    #[serde(default)]
+    pub manager_role: Option<u64>,
+    /// Whether assigned players receive their job in a direct message.
+    #[serde(default)]
+    pub direct_messages: bool,
+    #[serde(default)]
+    pub livesplit: LiveSplitConfig,
+    /// Prefix of commands, the instance's default if not set.
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// How slots are split between jobs when their amounts aren't whole.
+    #[serde(default)]
+    pub apportionment: Apportionment,
+    /// Whether qualifications registered in the bot also grant their Discord role.
+    #[serde(default)]
+    pub mirror_qualifications: bool,
+    /// Role of players excluded from role distribution, created on the first exclusion.
+    #[serde(default)]
+    pub excluded_role: Option<u64>,
+}
+
+/// Configuration of rolls triggered by a LiveSplit Server.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct LiveSplitConfig {
+    /// Last used server as `host:port`.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Names of splits which trigger a roll, every split if empty.
+    #[serde(default)]
+    pub splits: Vec<String>,
+    /// Whether resetting the run triggers a roll.
+    #[serde(default)]
+    pub roll_on_reset: bool,
+}
+
+const GUILDS_FILE: &str = "guilds.ron";
+
+pub type GuildConfigs = HashMap<u64, GuildConfig>;
+
+/// Loads guild configurations from file.
+pub fn load_guild_configs() -> GuildConfigs {
+    if let Ok(guilds_file) = File::open(data_path(GUILDS_FILE)) {
+        if let Ok(guilds) = ron::de::from_reader(guilds_file) {
+            return guilds;
+        }
+    }
+    HashMap::new()
+}
+
+/// Loads configuration of a single guild.
+pub fn load_guild_config(guild_id: u64) -> GuildConfig {
+    load_guild_configs().remove(&guild_id).unwrap_or_default()
+}
+
+/// Changes the configuration of a single guild as it is saved right now,
+/// so settings changed meanwhile by other tasks are kept.
+pub fn update_guild_config<R>(guild_id: u64, update: impl FnOnce(&mut GuildConfig) -> R) -> R {
+    update_ron(GUILDS_FILE, load_guild_configs, |guilds| {
+        update(guilds.entry(guild_id).or_default())
+    })
+}
+
+/// State of an ongoing session in a guild.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Session {
+    /// Session roles granted to players as `(user, role)` pairs.
+    #[serde(default)]
+    pub granted_roles: Vec<(u64, u64)>,
+    /// Voice channel players were moved out of.
+    #[serde(default)]
+    pub origin_channel: Option<u64>,
+    /// Players moved into job voice channels.
+    #[serde(default)]
+    pub moved_players: Vec<u64>,
+    /// Unix timestamp of when the session started, if one is running.
+    #[serde(default)]
+    pub started_at: Option<u64>,
+    /// Players taking part in the session.
+    #[serde(default)]
+    pub participants: Vec<u64>,
+    /// Assignments of every roll during the session.
+    #[serde(default)]
+    pub rounds: Vec<Round>,
+}
+
+impl Session {
+    pub fn is_active(&self) -> bool {
+        self.started_at.is_some()
+    }
+}
+
+/// Assignment of a single roll during a session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Round {
+    pub rolled_at: u64,
+    /// Players assigned to each job.
+    pub assigned: IndexMap<u64, Vec<u64>>,
+}
+
+const SESSIONS_FILE: &str = "sessions.ron";
+
+pub type Sessions = HashMap<u64, Session>;
+
+/// Loads sessions from file.
+pub fn load_sessions() -> Sessions {
+    if let Ok(sessions_file) = File::open(data_path(SESSIONS_FILE)) {
+        if let Ok(sessions) = ron::de::from_reader(sessions_file) {
+            return sessions;
+        }
+    }
+    HashMap::new()
+}
+
+/// Loads session of a single guild.
+pub fn load_session(guild_id: u64) -> Session {
+    load_sessions().remove(&guild_id).unwrap_or_default()
+}
+
+/// Waits until no other task changes the session of a guild.
+pub async fn lock_session(guild_id: u64) -> GuildLock {
+    lock_guild(SESSIONS_FILE, guild_id).await
+}
+
+/// Save session of a single guild.
+pub fn save_session(guild_id: u64, session: Session) {
+    update_ron(SESSIONS_FILE, load_sessions, |sessions| {
+        sessions.insert(guild_id, session);
+    });
+}
+
+/// Assignment made by a single successful roll.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RollRecord {
+    pub guild_id: u64,
+    pub rolled_at: u64,
+    /// Players assigned to each job.
+    pub assigned: IndexMap<u64, Vec<u64>>,
+}
+
+const HISTORY_FILE: &str = "history.ron";
+
+pub type History = Vec<RollRecord>;
+
+/// Loads roll history from file.
+pub fn load_history() -> History {
+    if let Ok(history_file) = File::open(data_path(HISTORY_FILE)) {
+        if let Ok(history) = ron::de::from_reader(history_file) {
+            return history;
+        }
     }
+    Vec::new()
+}
+
+/// Save roll history to file.
+pub fn save_history(history: History) {
+    save_ron(HISTORY_FILE, &history);
 }
 
-const EXCLUDED_PATH: &'static str = r"excluded.ron";
+const QUALIFICATIONS_FILE: &str = "qualifications.ron";
 
-/// Loads excluded role from file.
-pub fn load_excluded() -> Option<u64> {
-    if let Ok(excluded_file) = File::open(EXCLUDED_PATH) {
-        if let Ok(excluded) = ron::de::from_reader(excluded_file) {
-            return Some(excluded);
+/// Roles each player is qualified for in a guild, kept by the bot instead of Discord.
+pub type GuildQualifications = HashMap<u64, Vec<u64>>;
+
+pub type Qualifications = HashMap<u64, GuildQualifications>;
+
+/// Loads registered qualifications from file.
+pub fn load_qualifications() -> Qualifications {
+    if let Ok(qualifications_file) = File::open(data_path(QUALIFICATIONS_FILE)) {
+        if let Ok(qualifications) = ron::de::from_reader(qualifications_file) {
+            return qualifications;
         }
     }
-    None
-}
-
-/// Save excluded role to file.
-pub fn save_excluded(excluded: u64) {
-    if let Ok(excluded_file) = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(EXCLUDED_PATH)
-    {
-        let config = PrettyConfig::default();
-        ron::ser::to_writer_pretty(excluded_file, &excluded, config).ok();
+    HashMap::new()
+}
+
+/// Waits until no other task changes the registered qualifications of a guild.
+pub async fn lock_guild_qualifications(guild_id: u64) -> GuildLock {
+    lock_guild(QUALIFICATIONS_FILE, guild_id).await
+}
+
+/// Loads registered qualifications of a single guild.
+pub fn load_guild_qualifications(guild_id: u64) -> GuildQualifications {
+    load_qualifications().remove(&guild_id).unwrap_or_default()
+}
+
+/// Save registered qualifications of a single guild.
+pub fn save_guild_qualifications(guild_id: u64, guild_qualifications: GuildQualifications) {
+    update_ron(QUALIFICATIONS_FILE, load_qualifications, |qualifications| {
+        qualifications.insert(guild_id, guild_qualifications);
+    });
+}
+
+/// Removes a role from the qualifications registered in a guild,
+/// returns whether anything changed.
+pub async fn prune_qualifications(guild_id: u64, role_id: u64) -> bool {
+    let _lock = lock_guild_qualifications(guild_id).await;
+    let mut qualifications = load_guild_qualifications(guild_id);
+    let changed = prune_qualified_role(&mut qualifications, role_id);
+    if changed {
+        save_guild_qualifications(guild_id, qualifications);
+    }
+    changed
+}
+
+fn prune_qualified_role(qualifications: &mut GuildQualifications, role_id: u64) -> bool {
+    let mut changed = false;
+    for role_ids in qualifications.values_mut() {
+        let count = role_ids.len();
+        role_ids.retain(|registered| *registered != role_id);
+        changed |= role_ids.len() != count;
+    }
+    qualifications.retain(|_user_id, role_ids| !role_ids.is_empty());
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake::job;
+
+    #[test]
+    fn prunes_deleted_role_everywhere() {
+        let mut runner = job(&[(4, 1)]);
+        runner.session_role = Some(2);
+        runner.tiers = vec![3, 2];
+        let mut jobs = Jobs::new();
+        jobs.insert(1, runner);
+        jobs.insert(2, job(&[(4, 1)]));
+
+        assert!(prune_role(&mut jobs, 2));
+
+        assert!(!jobs.contains_key(&2));
+        assert_eq!(jobs[&1].session_role, None);
+        assert_eq!(jobs[&1].tiers, vec![3]);
+        assert!(!prune_role(&mut jobs, 2));
+    }
+
+    #[test]
+    fn prunes_deleted_role_from_qualifications() {
+        let mut qualifications = GuildQualifications::new();
+        qualifications.insert(10, vec![1, 2]);
+        qualifications.insert(11, vec![2]);
+
+        assert!(prune_qualified_role(&mut qualifications, 2));
+
+        assert_eq!(qualifications[&10], vec![1]);
+        assert!(!qualifications.contains_key(&11));
+        assert!(!prune_qualified_role(&mut qualifications, 2));
     }
 }
\ No newline at end of file
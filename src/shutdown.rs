@@ -0,0 +1,136 @@
+//! Stopping the bot without losing work when the process is asked to exit.
+
+use serenity::{
+    client::bridge::gateway::ShardManager, model::prelude::*, prelude::*, CacheAndHttp,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::{sleep, Instant};
+
+use crate::{
+    commands::{
+        guild::DiscordGuild, livesplit::disconnect_all, schedule::cancel_all_rolls,
+        session::revoke_session_roles,
+    },
+    model::{load_session, load_sessions, lock_session, save_session},
+};
+
+/// Longest wait for running commands to finish.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+const DRAIN_POLL: Duration = Duration::from_millis(100);
+
+/// Whether the bot is stopping and how many commands are still running.
+#[derive(Default)]
+pub struct Shutdown {
+    stopping: AtomicBool,
+    running: AtomicUsize,
+}
+
+impl TypeMapKey for Shutdown {
+    type Value = Arc<Shutdown>;
+}
+
+impl Shutdown {
+    /// Registers a starting command, refused once the bot is stopping.
+    pub fn command_started(&self) -> bool {
+        if self.stopping.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.running.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    pub fn command_finished(&self) {
+        self.running.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Stops accepting commands and waits for running ones to finish.
+    async fn drain(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        while self.running.load(Ordering::SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                tracing::warn!(
+                    running = self.running.load(Ordering::SeqCst),
+                    "stopping with commands still running"
+                );
+                return;
+            }
+            sleep(DRAIN_POLL).await;
+        }
+    }
+}
+
+/// Shared state of the bot from its context.
+pub async fn get(ctx: &Context) -> Arc<Shutdown> {
+    ctx.data
+        .read()
+        .await
+        .get::<Shutdown>()
+        .cloned()
+        .expect("Shutdown state is inserted at startup")
+}
+
+/// Waits for SIGINT or SIGTERM, then stops scheduled and LiveSplit rolls,
+/// finishes running commands, removes session roles and disconnects all shards.
+pub async fn on_signal(
+    shutdown: Arc<Shutdown>,
+    data: Arc<RwLock<TypeMap>>,
+    cache_and_http: Arc<CacheAndHttp>,
+    shard_manager: Arc<Mutex<ShardManager>>,
+) {
+    wait_for_signal().await;
+    tracing::info!("shutting down");
+
+    // Rolls started by these would grant session roles again.
+    cancel_all_rolls(&data).await;
+    disconnect_all(&data).await;
+    shutdown.drain().await;
+    for guild_id in load_sessions().into_keys() {
+        let _lock = lock_session(guild_id).await;
+        let mut session = load_session(guild_id);
+        if !session.granted_roles.is_empty() {
+            let guild = DiscordGuild::from_cache_and_http(&cache_and_http, GuildId(guild_id));
+            revoke_session_roles(&guild, &mut session).await;
+            save_session(guild_id, session);
+        }
+    }
+    shard_manager.lock().await.shutdown_all().await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    tokio::signal::ctrl_c().await.ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_refuses_new_commands() {
+        let shutdown = Shutdown::default();
+        assert!(shutdown.command_started());
+        shutdown.command_finished();
+
+        shutdown.drain().await;
+
+        assert!(!shutdown.command_started());
+    }
+}